@@ -0,0 +1,54 @@
+//! Runs the Mehl programs in the `benches` directory a few times and reports
+//! how long they took. Run it using `cargo bench`.
+
+use mehl::ast::Ast;
+use mehl::runner::{Chunk, Context, Runtime};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+fn parse(path: &Path) -> Rc<Chunk> {
+    let source = std::fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Couldn't read {}.", path.display()));
+    let asts =
+        Ast::parse_all(&source).unwrap_or_else(|_| panic!("Couldn't parse {}.", path.display()));
+    Chunk::compile(asts)
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let core = parse(&root.join("core.mehl"));
+    let mut programs = std::fs::read_dir(root.join("benches"))
+        .expect("Couldn't read the benches directory.")
+        .map(|entry| entry.expect("Couldn't read a bench.").path())
+        .filter(|path| path.extension() == Some("mehl".as_ref()))
+        .collect::<Vec<_>>();
+    programs.sort();
+
+    for path in programs {
+        let program = parse(&path);
+        let mut durations = (0..RUNS)
+            .map(|_| {
+                let mut runtime = Runtime::default();
+                let context = match Context::root(&mut runtime).run(&mut runtime, core.clone()) {
+                    Ok(context) => context,
+                    Err(error) => panic!("The core library panicked: {}", error),
+                };
+                let start = Instant::now();
+                if let Err(error) = context.run(&mut runtime, program.clone()) {
+                    panic!("{} panicked: {}", path.display(), error);
+                }
+                start.elapsed()
+            })
+            .collect::<Vec<Duration>>();
+        durations.sort();
+        println!(
+            "{:<20} {:>10.2?} (median of {} runs)",
+            path.file_stem().unwrap().to_string_lossy(),
+            durations[RUNS / 2],
+            RUNS
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use super::Span;
+use std::fmt;
+
+/// A node of the concrete syntax tree. Unlike ASTs, CSTs contain everything
+/// from the source, including whitespace, comments, commas and the quotes of
+/// strings, so displaying them reproduces the original code byte for byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cst {
+    pub kind: CstKind,
+    pub span: Span,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CstKind {
+    Whitespace(String),
+    /// A comment including the leading `#`, but without the line break.
+    Comment(String),
+    Comma,
+    /// The literal text of a number, like `36rax9z3l1m6`.
+    Number(String),
+    /// A string like `"Hi, {name}!"` or `'"Foo"'`. The parts are the
+    /// `StringText`s and `Interpolation`s between the quotes.
+    String {
+        single_quotes: usize,
+        parts: Vec<Cst>,
+        is_terminated: bool,
+    },
+    /// Text inside a string, with escape sequences left as they are.
+    StringText(String),
+    /// Code in braces inside a string, like `{name}`.
+    Interpolation {
+        children: Vec<Cst>,
+        is_closed: bool,
+    },
+    /// A symbol without the leading colon.
+    Symbol(String),
+    Name(String),
+    List {
+        children: Vec<Cst>,
+        is_closed: bool,
+    },
+    Map {
+        children: Vec<Cst>,
+        is_closed: bool,
+    },
+    Code {
+        children: Vec<Cst>,
+        is_closed: bool,
+    },
+    /// Text that couldn't be parsed.
+    Error(String),
+}
+
+impl Cst {
+    pub fn new(kind: CstKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind, CstKind::Whitespace(_) | CstKind::Comment(_))
+    }
+
+    pub fn children(&self) -> &[Cst] {
+        match &self.kind {
+            CstKind::String {
+                parts: children, ..
+            }
+            | CstKind::Interpolation { children, .. }
+            | CstKind::List { children, .. }
+            | CstKind::Map { children, .. }
+            | CstKind::Code { children, .. } => children,
+            _ => &[],
+        }
+    }
+}
+
+/// Reproduces the source code of the CSTs.
+pub fn to_source(csts: &[Cst]) -> String {
+    itertools::join(csts, "")
+}
+
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bracketed = |f: &mut fmt::Formatter<'_>,
+                         opening: char,
+                         closing: char,
+                         children: &[Cst],
+                         is_closed: bool| {
+            write!(f, "{}{}", opening, to_source(children))?;
+            if is_closed {
+                write!(f, "{}", closing)?;
+            }
+            Ok(())
+        };
+        match &self.kind {
+            CstKind::Whitespace(whitespace) => write!(f, "{}", whitespace),
+            CstKind::Comment(comment) => write!(f, "{}", comment),
+            CstKind::Comma => write!(f, ","),
+            CstKind::Number(number) => write!(f, "{}", number),
+            CstKind::String {
+                single_quotes,
+                parts,
+                is_terminated,
+            } => {
+                let quotes = "'".repeat(*single_quotes);
+                write!(f, "{}\"{}", quotes, to_source(parts))?;
+                if *is_terminated {
+                    write!(f, "\"{}", quotes)?;
+                }
+                Ok(())
+            }
+            CstKind::StringText(text) => write!(f, "{}", text),
+            CstKind::Interpolation {
+                children,
+                is_closed,
+            } => bracketed(f, '{', '}', children, *is_closed),
+            CstKind::Symbol(symbol) => write!(f, ":{}", symbol),
+            CstKind::Name(name) => write!(f, "{}", name),
+            CstKind::List {
+                children,
+                is_closed,
+            } => bracketed(f, '(', ')', children, *is_closed),
+            CstKind::Map {
+                children,
+                is_closed,
+            } => bracketed(f, '{', '}', children, *is_closed),
+            CstKind::Code {
+                children,
+                is_closed,
+            } => bracketed(f, '[', ']', children, *is_closed),
+            CstKind::Error(text) => write!(f, "{}", text),
+        }
+    }
+}
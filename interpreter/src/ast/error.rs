@@ -0,0 +1,226 @@
+use super::{Position, Span};
+use colored::Colorize;
+use lspower::lsp;
+use std::fmt;
+
+/// Something that went wrong while parsing Mehl code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub file: Option<String>,
+    pub span: Span,
+    pub kind: ParseErrorKind,
+    pub expected: Vec<Expected>,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnterminatedString,
+    MissingDoubleQuote,
+    InvalidEscapeSequence(String),
+    EmptyInterpolation,
+    OddMapEntryCount,
+    InvalidDigit {
+        digit: char,
+        radix: usize,
+    },
+    /// The radix as written in the source, which may be too big for a `usize`.
+    InvalidRadix(String),
+    MissingDigits,
+    ZeroDenominator,
+    Unclosed {
+        bracket: char,
+        opened: Position,
+    },
+    MissingListItem,
+    MissingMapItem,
+    UnexpectedCharacter(char),
+    /// More than [`MAX_NESTING`](super::MAX_NESTING) brackets are open.
+    NestedTooDeeply,
+    /// The source is longer than [`MAX_SOURCE_LEN`](super::MAX_SOURCE_LEN).
+    SourceTooLong,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Text(String),
+    Digit { radix: usize },
+    Ast,
+}
+
+impl ParseError {
+    pub fn new(span: Span, kind: ParseErrorKind, expected: Vec<Expected>) -> Self {
+        Self {
+            file: None,
+            span,
+            kind,
+            expected,
+        }
+    }
+
+    pub fn in_file<S: Into<String>>(mut self, file: S) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    fn expected_message(&self) -> Option<String> {
+        match self.expected.as_slice() {
+            [] => None,
+            [expected] => Some(format!("expected {}", expected)),
+            [init @ .., last] => Some(format!(
+                "expected {} or {}",
+                itertools::join(init, ", "),
+                last
+            )),
+        }
+    }
+
+    /// Renders the error for the terminal, showing the offending line of the
+    /// source with a caret under the problematic part.
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start;
+        let line = source.lines().nth(start.line as usize).unwrap_or("");
+        let underline_len = if self.span.end.line == start.line {
+            (self.span.end.column - start.column).max(1)
+        } else {
+            (line.chars().count() as u32 - start.column).max(1)
+        };
+        let line_number = (start.line + 1).to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut out = format!("{}{} {}\n", "error".red().bold(), ":".bold(), self.kind);
+        out.push_str(&format!(
+            "{}{} {}:{}\n",
+            gutter,
+            "-->".blue().bold(),
+            self.file.as_deref().unwrap_or("<input>"),
+            start,
+        ));
+        out.push_str(&format!("{} {}\n", gutter, "|".blue().bold()));
+        out.push_str(&format!(
+            "{} {} {}\n",
+            line_number.blue().bold(),
+            "|".blue().bold(),
+            line
+        ));
+        out.push_str(&format!(
+            "{} {} {}{}",
+            gutter,
+            "|".blue().bold(),
+            " ".repeat(start.column as usize),
+            "^".repeat(underline_len as usize).red().bold()
+        ));
+        if let Some(expected) = self.expected_message() {
+            out.push_str(&format!(" {}", expected.red().bold()));
+        }
+        if let ParseErrorKind::Unclosed { opened, .. } = &self.kind {
+            out.push_str(&format!(
+                "\n{} {} opened at {}",
+                gutter,
+                "=".blue().bold(),
+                opened
+            ));
+        }
+        out
+    }
+
+    /// The error as an LSP diagnostic. Needs the source the error is from,
+    /// because LSP columns count UTF-16 code units.
+    pub fn to_diagnostic(&self, source: &str) -> lsp::Diagnostic {
+        let message = match self.expected_message() {
+            Some(expected) => format!("{}\n{}", self.kind, expected),
+            None => self.kind.to_string(),
+        };
+        lsp::Diagnostic {
+            range: span_to_range(source, self.span),
+            severity: Some(lsp::DiagnosticSeverity::Error),
+            source: Some("mehl".into()),
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+/// Turns a span in the source into an LSP range. Unlike our columns, which
+/// count chars, LSP columns count UTF-16 code units, so a `🌮` is two wide.
+pub fn span_to_range(source: &str, span: Span) -> lsp::Range {
+    let position = |position: Position| {
+        let offset = position.offset as usize;
+        let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+        lsp::Position {
+            line: position.line,
+            character: source[line_start..offset].encode_utf16().count() as u32,
+        }
+    };
+    lsp::Range {
+        start: position(span.start),
+        end: position(span.end),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.file.as_deref().unwrap_or("<input>"),
+            self.span.start,
+            self.kind
+        )?;
+        if let Some(expected) = self.expected_message() {
+            write!(f, " ({})", expected)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnterminatedString => write!(f, "String started, but didn't end."),
+            ParseErrorKind::MissingDoubleQuote => {
+                write!(f, "Expected double quote after single quotes.")
+            }
+            ParseErrorKind::InvalidEscapeSequence(sequence) => {
+                write!(f, "'{}' is not a valid escape sequence.", sequence)
+            }
+            ParseErrorKind::EmptyInterpolation => {
+                write!(f, "Expected an expression in the interpolation.")
+            }
+            ParseErrorKind::OddMapEntryCount => {
+                write!(f, "Maps have to contain an even number of elements.")
+            }
+            ParseErrorKind::InvalidDigit { digit, radix } => write!(
+                f,
+                "The character '{}' is not a valid digit in radix {}.",
+                digit, radix
+            ),
+            ParseErrorKind::InvalidRadix(radix) => {
+                write!(f, "Radix {} is invalid, it has to be from 2 to 62.", radix)
+            }
+            ParseErrorKind::MissingDigits => write!(f, "Expected digits here."),
+            ParseErrorKind::ZeroDenominator => {
+                write!(f, "The denominator of a fraction can't be zero.")
+            }
+            ParseErrorKind::Unclosed { bracket, .. } => write!(f, "Unclosed '{}'.", bracket),
+            ParseErrorKind::MissingListItem => write!(f, "Expected a list item here."),
+            ParseErrorKind::MissingMapItem => write!(f, "Expected a map key or value here."),
+            ParseErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'.", c),
+            ParseErrorKind::NestedTooDeeply => write!(
+                f,
+                "The code is nested too deeply, at most {} brackets can be open.",
+                super::MAX_NESTING
+            ),
+            ParseErrorKind::SourceTooLong => write!(
+                f,
+                "The source is too long, it can have at most {} bytes.",
+                super::MAX_SOURCE_LEN
+            ),
+        }
+    }
+}
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Text(text) => write!(f, "`{}`", text),
+            Expected::Digit { radix } => write!(f, "a digit in radix {}", radix),
+            Expected::Ast => write!(f, "an expression"),
+        }
+    }
+}
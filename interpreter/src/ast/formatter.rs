@@ -0,0 +1,433 @@
+use super::*;
+
+const MAX_WIDTH: usize = 80;
+const INDENT: &str = "  ";
+
+/// Formats Mehl code canonically. Comments are preserved, as well as line
+/// breaks between code in blocks (although consecutive blank lines are merged).
+/// Lists, maps and code blocks are put on a single line if they fit and broken
+/// into multiple lines otherwise. Formatting already formatted code doesn't
+/// change it.
+///
+/// Code that has parse errors is not formatted.
+pub fn format_source(source: &str) -> Result<String, Vec<ParseError>> {
+    let (_, errors) = Ast::parse(source);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let (csts, _) = Cst::parse(source);
+    let nodes = csts.iter().map(measure).collect::<Vec<_>>();
+    let mut formatted = render_block(&nodes, 0);
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+/// A CST together with its width on a single line. The widths are measured
+/// once, bottom-up, so that deciding where to break lines doesn't render the
+/// same code over and over.
+struct Node<'a> {
+    cst: &'a Cst,
+    /// `None` if the node can't be put on a single line because it contains
+    /// comments, a code block with multiple lines or a line break in a string.
+    width: Option<usize>,
+    children: Vec<Node<'a>>,
+}
+
+fn measure(cst: &Cst) -> Node<'_> {
+    let children = match &cst.kind {
+        CstKind::List { children, .. }
+        | CstKind::Map { children, .. }
+        | CstKind::Code { children, .. } => children.iter().map(measure).collect(),
+        _ => vec![],
+    };
+    let nodes_width = |nodes: &[&Node]| -> Option<usize> {
+        let widths = nodes.iter().map(|node| node.width).sum::<Option<usize>>()?;
+        Some(widths + nodes.len().saturating_sub(1))
+    };
+    let items_width = |children: &[Node]| -> Option<usize> {
+        let (items, dangling_comments) = items_of(children);
+        if !dangling_comments.is_empty() {
+            return None;
+        }
+        let mut width = 2 * items.len().saturating_sub(1);
+        for item in &items {
+            if !item.leading_comments.is_empty() || !item.trailing_comments.is_empty() {
+                return None;
+            }
+            width += nodes_width(&item.nodes)?;
+        }
+        Some(width)
+    };
+    let width = match &cst.kind {
+        CstKind::List { .. } | CstKind::Map { .. } => items_width(&children).map(|it| it + 2),
+        CstKind::Code { .. } => match lines_of_block(&children).as_slice() {
+            [] => Some(2),
+            [Line::Code {
+                nodes,
+                comment: None,
+            }] => nodes_width(nodes).map(|it| it + 2),
+            _ => None,
+        },
+        _ => {
+            let text = cst.to_string();
+            if text.contains('\n') {
+                None
+            } else {
+                Some(width(&text))
+            }
+        }
+    };
+    Node {
+        cst,
+        width,
+        children,
+    }
+}
+
+/// A line in a block of code, like the top level of a file or the body of a
+/// code block.
+enum Line<'n, 'a> {
+    Blank,
+    Code {
+        nodes: Vec<&'n Node<'a>>,
+        comment: Option<&'a str>,
+    },
+    Comment(&'a str),
+}
+
+fn lines_of_block<'n, 'a>(children: &'n [Node<'a>]) -> Vec<Line<'n, 'a>> {
+    let mut lines = vec![];
+    let mut nodes = vec![];
+    for child in children {
+        match &child.cst.kind {
+            CstKind::Whitespace(whitespace) => {
+                let line_breaks = whitespace.matches('\n').count();
+                if line_breaks == 0 {
+                    continue;
+                }
+                if !nodes.is_empty() {
+                    lines.push(Line::Code {
+                        nodes: std::mem::take(&mut nodes),
+                        comment: None,
+                    });
+                }
+                if line_breaks > 1 {
+                    lines.push(Line::Blank);
+                }
+            }
+            CstKind::Comment(comment) => {
+                if nodes.is_empty() {
+                    lines.push(Line::Comment(comment));
+                } else {
+                    lines.push(Line::Code {
+                        nodes: std::mem::take(&mut nodes),
+                        comment: Some(comment),
+                    });
+                }
+            }
+            _ => nodes.push(child),
+        }
+    }
+    if !nodes.is_empty() {
+        lines.push(Line::Code {
+            nodes,
+            comment: None,
+        });
+    }
+
+    // Remove blank lines at the start, at the end, and after other blank lines.
+    let mut result: Vec<Line> = vec![];
+    for line in lines {
+        let is_redundant =
+            matches!(line, Line::Blank) && matches!(result.last(), None | Some(Line::Blank));
+        if !is_redundant {
+            result.push(line);
+        }
+    }
+    if let Some(Line::Blank) = result.last() {
+        result.pop();
+    }
+    result
+}
+
+fn render_block(children: &[Node], indent: usize) -> String {
+    let prefix = INDENT.repeat(indent);
+    let lines = lines_of_block(children).into_iter().map(|line| match line {
+        Line::Blank => "".to_string(),
+        Line::Comment(comment) => format!("{}{}", prefix, comment.trim_end()),
+        Line::Code { nodes, comment } => {
+            let mut line = format!("{}{}", prefix, render_line(&nodes, indent, prefix.len()));
+            if let Some(comment) = comment {
+                line.push(' ');
+                line.push_str(comment.trim_end());
+            }
+            line
+        }
+    });
+    itertools::join(lines, "\n")
+}
+
+/// Splits off a comment on the same line as the opening bracket of a list, map
+/// or code block.
+fn opening_comment<'n, 'a>(children: &'n [Node<'a>]) -> (Option<&'a str>, &'n [Node<'a>]) {
+    for (i, child) in children.iter().enumerate() {
+        match &child.cst.kind {
+            CstKind::Whitespace(whitespace) if !whitespace.contains('\n') => {}
+            CstKind::Comment(comment) => return (Some(comment), &children[i + 1..]),
+            _ => break,
+        }
+    }
+    (None, children)
+}
+
+/// An item of a list or map, together with the comments around it.
+#[derive(Default)]
+struct Item<'n, 'a> {
+    leading_comments: Vec<&'a str>,
+    nodes: Vec<&'n Node<'a>>,
+    trailing_comments: Vec<&'a str>,
+}
+
+/// Splits the children of a list or map into items. Comments on the same line
+/// as an item are attached to it, other comments to the following item.
+fn items_of<'n, 'a>(children: &'n [Node<'a>]) -> (Vec<Item<'n, 'a>>, Vec<&'a str>) {
+    let mut items = vec![];
+    let mut current = Item::default();
+    let mut is_on_line_of_previous_item = false;
+    for child in children {
+        match &child.cst.kind {
+            CstKind::Whitespace(whitespace) => {
+                if whitespace.contains('\n') {
+                    is_on_line_of_previous_item = false;
+                }
+            }
+            CstKind::Comment(comment) => {
+                if !current.nodes.is_empty() {
+                    current.trailing_comments.push(comment);
+                } else if is_on_line_of_previous_item {
+                    let previous: &mut Item = items.last_mut().unwrap();
+                    previous.trailing_comments.push(comment);
+                } else {
+                    current.leading_comments.push(comment);
+                }
+            }
+            CstKind::Comma => {
+                items.push(std::mem::take(&mut current));
+                is_on_line_of_previous_item = true;
+            }
+            _ => current.nodes.push(child),
+        }
+    }
+    if !current.nodes.is_empty() {
+        items.push(current);
+        return (items, vec![]);
+    }
+    (items, current.leading_comments)
+}
+
+/// Writes a node that has a width on a single line.
+fn write_inline(node: &Node, out: &mut String) {
+    let write_nodes = |nodes: &[&Node], out: &mut String| {
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            write_inline(node, out);
+        }
+    };
+    let write_items = |children: &[Node], out: &mut String| {
+        for (i, item) in items_of(children).0.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write_nodes(&item.nodes, out);
+        }
+    };
+    match &node.cst.kind {
+        CstKind::List { .. } => {
+            out.push('(');
+            write_items(&node.children, out);
+            out.push(')');
+        }
+        CstKind::Map { .. } => {
+            out.push('{');
+            write_items(&node.children, out);
+            out.push('}');
+        }
+        CstKind::Code { .. } => {
+            out.push('[');
+            if let [Line::Code { nodes, .. }] = lines_of_block(&node.children).as_slice() {
+                write_nodes(nodes, out);
+            }
+            out.push(']');
+        }
+        _ => out.push_str(&node.cst.to_string()),
+    }
+}
+
+fn is_group(cst: &Cst) -> bool {
+    matches!(
+        cst.kind,
+        CstKind::List { .. } | CstKind::Map { .. } | CstKind::Code { .. }
+    )
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// The column at which the text ends if it starts at the given column.
+fn column_after(text: &str, column: usize) -> usize {
+    match text.rfind('\n') {
+        Some(index) => width(&text[index + 1..]),
+        None => column + width(text),
+    }
+}
+
+/// The width that the nodes need at least on the current line, because they
+/// come before the next list, map or code block that could be broken.
+fn width_until_break(nodes: &[&Node]) -> usize {
+    let mut total = 0;
+    for node in nodes {
+        match node.width {
+            Some(width) if !is_group(node.cst) => total += 1 + width,
+            // A space and the opening bracket.
+            _ if is_group(node.cst) => return total + 2,
+            _ => return total,
+        }
+    }
+    total
+}
+
+/// Renders nodes that are on the same line, starting at the given column.
+/// Lists, maps and code blocks stay on the line if they fit together with the
+/// nodes after them up to the next one that can be broken. Otherwise, they are
+/// broken into multiple lines.
+fn render_line(nodes: &[&Node], indent: usize, column: usize) -> String {
+    let mut rendered = String::new();
+    let mut column = column;
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            rendered.push(' ');
+            column += 1;
+        }
+        let fits = match node.width {
+            Some(width) if is_group(node.cst) => {
+                column + width + width_until_break(&nodes[i + 1..]) <= MAX_WIDTH
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if fits {
+            let start = rendered.len();
+            write_inline(node, &mut rendered);
+            column += width(&rendered[start..]);
+        } else {
+            let broken = render_broken(node, indent);
+            column = column_after(&broken, column);
+            rendered.push_str(&broken);
+        }
+    }
+    rendered
+}
+
+/// Renders a list, map or code block over multiple lines.
+fn render_broken(node: &Node, indent: usize) -> String {
+    let prefix = INDENT.repeat(indent);
+    let inner_prefix = INDENT.repeat(indent + 1);
+    let column = inner_prefix.len();
+    let (comment, children) = opening_comment(&node.children);
+    let (opening, closing, body) = match &node.cst.kind {
+        CstKind::List { .. } => {
+            let (items, dangling_comments) = items_of(children);
+            let mut lines = vec![];
+            for item in items {
+                lines.extend(
+                    item.leading_comments
+                        .iter()
+                        .map(|it| it.trim_end().to_string()),
+                );
+                let mut line = format!("{},", render_line(&item.nodes, indent + 1, column));
+                push_comments(&mut line, &item.trailing_comments);
+                lines.push(line);
+            }
+            lines.extend(dangling_comments.iter().map(|it| it.trim_end().to_string()));
+            ('(', ')', indent_lines(&lines, &inner_prefix))
+        }
+        CstKind::Map { .. } => {
+            let (items, dangling_comments) = items_of(children);
+            let mut lines = vec![];
+            for pair in items.chunks(2) {
+                let key = &pair[0];
+                lines.extend(
+                    key.leading_comments
+                        .iter()
+                        .map(|it| it.trim_end().to_string()),
+                );
+                let mut line = format!("{},", render_line(&key.nodes, indent + 1, column));
+                let mut comments = key.trailing_comments.clone();
+                if let Some(value) = pair.get(1) {
+                    // A value with comments before it goes on its own line, so
+                    // the comments stay right above it.
+                    if value.leading_comments.is_empty() {
+                        line.push(' ');
+                    } else {
+                        push_comments(&mut line, &comments);
+                        lines.push(line);
+                        lines.extend(
+                            value
+                                .leading_comments
+                                .iter()
+                                .map(|it| it.trim_end().to_string()),
+                        );
+                        line = String::new();
+                        comments.clear();
+                    }
+                    let column = column_after(&line, column);
+                    line.push_str(&render_line(&value.nodes, indent + 1, column));
+                    line.push(',');
+                    comments.extend(value.trailing_comments.iter());
+                }
+                push_comments(&mut line, &comments);
+                lines.push(line);
+            }
+            lines.extend(dangling_comments.iter().map(|it| it.trim_end().to_string()));
+            ('{', '}', indent_lines(&lines, &inner_prefix))
+        }
+        CstKind::Code { .. } => ('[', ']', render_block(children, indent + 1)),
+        _ => return node.cst.to_string(),
+    };
+    let mut rendered = opening.to_string();
+    if let Some(comment) = comment {
+        rendered.push(' ');
+        rendered.push_str(comment.trim_end());
+    }
+    if !body.is_empty() {
+        rendered.push('\n');
+        rendered.push_str(&body);
+    }
+    if comment.is_some() || !body.is_empty() {
+        rendered.push('\n');
+        rendered.push_str(&prefix);
+    }
+    rendered.push(closing);
+    rendered
+}
+
+fn push_comments(line: &mut String, comments: &[&str]) {
+    for comment in comments {
+        line.push(' ');
+        line.push_str(comment.trim_end());
+    }
+}
+
+fn indent_lines<S: AsRef<str>>(lines: &[S], prefix: &str) -> String {
+    itertools::join(
+        lines
+            .iter()
+            .map(|line| format!("{}{}", prefix, line.as_ref())),
+        "\n",
+    )
+}
@@ -0,0 +1,349 @@
+use super::*;
+use crate::number::{Int, Number, Rational};
+use num_bigint::BigInt;
+use num_traits::{One, ToPrimitive};
+
+/// Lowers CSTs to ASTs, dropping whitespace, comments and commas. Problems that
+/// only become apparent when looking at the meaning of the code, like invalid
+/// digits in numbers or maps with an odd number of elements, are reported here.
+pub fn lower(csts: &[Cst]) -> (Asts, Vec<ParseError>) {
+    let mut errors = vec![];
+    let asts = lower_csts(csts, &mut errors);
+    (asts, errors)
+}
+
+fn lower_csts(csts: &[Cst], errors: &mut Vec<ParseError>) -> Asts {
+    csts.iter()
+        .filter_map(|cst| Some(Ast::new(lower_cst(cst, errors)?, cst.span)))
+        .collect()
+}
+
+/// Lowers a single CST. Returns `None` for whitespace, comments and commas.
+fn lower_cst(cst: &Cst, errors: &mut Vec<ParseError>) -> Option<AstKind> {
+    Some(match &cst.kind {
+        CstKind::Whitespace(_) | CstKind::Comment(_) | CstKind::Comma => return None,
+        // The parser already reported stray closing brackets and commas. They
+        // don't stand for any code, so they are skipped.
+        CstKind::Error(text) if matches!(text.as_str(), ")" | "]" | "}" | ",") => return None,
+        CstKind::Number(number) => match lower_number(number, cst.span) {
+            Ok(number) => AstKind::Number(number),
+            Err(error) => {
+                errors.push(error);
+                AstKind::Error
+            }
+        },
+        CstKind::String {
+            single_quotes,
+            parts,
+            is_terminated,
+        } => {
+            if !*is_terminated {
+                AstKind::Error
+            } else if *single_quotes > 0 {
+                AstKind::String(to_source(parts))
+            } else {
+                lower_string(parts, errors)
+            }
+        }
+        CstKind::Symbol(symbol) => AstKind::Symbol(symbol.clone()),
+        CstKind::Name(name) => AstKind::Name(name.clone()),
+        CstKind::List { children, .. } => {
+            let items = items(children, errors);
+            let mut list = vec![];
+            let number_of_items = items.len();
+            for (i, (item, comma)) in items.into_iter().enumerate() {
+                if !item.is_empty() {
+                    list.push(item);
+                } else if i + 1 < number_of_items {
+                    errors.push(ParseError::new(
+                        comma.expect("Only the last item has no comma after it."),
+                        ParseErrorKind::MissingListItem,
+                        vec![Expected::Ast],
+                    ));
+                }
+            }
+            AstKind::List(list)
+        }
+        CstKind::Map { children, .. } => {
+            let mut items = items(children, errors);
+            if matches!(items.last(), Some((item, _)) if item.is_empty()) {
+                items.pop();
+            }
+            for (item, comma) in &items {
+                if item.is_empty() {
+                    errors.push(ParseError::new(
+                        comma.expect("Only the last item has no comma after it."),
+                        ParseErrorKind::MissingMapItem,
+                        vec![Expected::Ast],
+                    ));
+                }
+            }
+            let mut items = items.into_iter().map(|(item, _)| item).collect::<Vec<_>>();
+            if items.len() % 2 != 0 {
+                errors.push(ParseError::new(
+                    cst.span,
+                    ParseErrorKind::OddMapEntryCount,
+                    vec![],
+                ));
+                let end = Span::new(cst.span.end, cst.span.end);
+                items.push(vec![Ast::new(AstKind::Error, end)]);
+            }
+            let mut map = vec![];
+            let mut items = items.into_iter();
+            while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                map.push((key, value));
+            }
+            AstKind::Map(map)
+        }
+        CstKind::Code { children, .. } => AstKind::Code(lower_csts(children, errors)),
+        CstKind::Error(_) => AstKind::Error,
+        CstKind::StringText(_) | CstKind::Interpolation { .. } => {
+            unreachable!("String parts are lowered together with their string.")
+        }
+    })
+}
+
+/// Lowers the parts of a string without single quotes, resolving escape
+/// sequences. Strings with interpolations become `InterpolatedString`s.
+fn lower_string(parts: &[Cst], errors: &mut Vec<ParseError>) -> AstKind {
+    let mut string_parts = vec![];
+    let mut text = String::new();
+    for part in parts {
+        match &part.kind {
+            CstKind::StringText(raw) => text.push_str(&unescape(raw, part.span, errors)),
+            CstKind::Interpolation { children, .. } => {
+                if !text.is_empty() {
+                    string_parts.push(StringPart::Text(std::mem::take(&mut text)));
+                }
+                let mut code = lower_csts(children, errors);
+                if code.is_empty() {
+                    errors.push(ParseError::new(
+                        part.span,
+                        ParseErrorKind::EmptyInterpolation,
+                        vec![Expected::Ast],
+                    ));
+                    code.push(Ast::new(AstKind::Error, part.span));
+                }
+                string_parts.push(StringPart::Code(code));
+            }
+            _ => unreachable!("Strings only contain text and interpolations."),
+        }
+    }
+    if string_parts.is_empty() {
+        return AstKind::String(text);
+    }
+    if !text.is_empty() {
+        string_parts.push(StringPart::Text(text));
+    }
+    AstKind::InterpolatedString(string_parts)
+}
+
+/// Resolves the escape sequences `\n`, `\r`, `\t`, `\0`, `\\`, `\"`, `\{`,
+/// `\}` and `\u{...}` with one to six hex digits in the text of a string.
+fn unescape(raw: &str, span: Span, errors: &mut Vec<ParseError>) -> String {
+    // Unlike numbers, strings can span multiple lines.
+    let position = |index: usize| {
+        let before = &raw[..index];
+        match before.rfind('\n') {
+            Some(line_break) => Position {
+                offset: span.start.offset + index as u32,
+                line: span.start.line + before.matches('\n').count() as u32,
+                column: before[line_break + 1..].chars().count() as u32,
+            },
+            None => Position {
+                offset: span.start.offset + index as u32,
+                line: span.start.line,
+                column: span.start.column + before.chars().count() as u32,
+            },
+        }
+    };
+
+    let mut text = String::new();
+    let mut rest = raw;
+    while let Some(backslash) = rest.find('\\') {
+        text.push_str(&rest[..backslash]);
+        let start = raw.len() - rest.len() + backslash;
+        let sequence = &rest[backslash + 1..];
+        let (c, len) = match sequence.chars().next() {
+            Some('n') => (Some('\n'), 1),
+            Some('r') => (Some('\r'), 1),
+            Some('t') => (Some('\t'), 1),
+            Some('0') => (Some('\0'), 1),
+            Some(c @ ('\\' | '"' | '{' | '}')) => (Some(c), 1),
+            Some('u') => match sequence[1..]
+                .strip_prefix('{')
+                .and_then(|code| Some(&code[..code.find('}')?]))
+            {
+                Some(code) => {
+                    let c = Some(code)
+                        .filter(|code| (1..=6).contains(&code.len()))
+                        .and_then(|code| u32::from_str_radix(code, 16).ok())
+                        .and_then(char::from_u32);
+                    (c, code.len() + 3)
+                }
+                None => (None, 1),
+            },
+            Some(c) => (None, c.len_utf8()),
+            None => (None, 0),
+        };
+        let end = start + 1 + len;
+        match c {
+            Some(c) => text.push(c),
+            None => errors.push(ParseError::new(
+                Span::new(position(start), position(end)),
+                ParseErrorKind::InvalidEscapeSequence(raw[start..end].to_owned()),
+                vec![],
+            )),
+        }
+        rest = &raw[end..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Splits the children of a list or map at the commas. Returns the lowered
+/// items together with the span of the comma after them, if any.
+fn items(children: &[Cst], errors: &mut Vec<ParseError>) -> Vec<(Asts, Option<Span>)> {
+    let mut items = vec![];
+    let mut current = vec![];
+    for child in children {
+        if let CstKind::Comma = child.kind {
+            items.push((lower_csts(&current, errors), Some(child.span)));
+            current.clear();
+        } else {
+            current.push(child.clone());
+        }
+    }
+    items.push((lower_csts(&current, errors), None));
+    items
+}
+
+/// Parses the text of a number. A number consists of an optional minus, an
+/// optional radix like `16r` and digits in that radix, which may be followed
+/// by decimal places like in `3.14` or by a denominator like in `1/3`.
+/// Underscores between digits are ignored, so `1_000_000` is a million.
+///
+/// Numbers with decimal places or a denominator are exact rationals, unless
+/// their value is an integer.
+fn lower_number(text: &str, span: Span) -> Result<Number, ParseError> {
+    // Numbers never contain line breaks, so positions inside them are easy to
+    // calculate.
+    let position = |index: usize| Position {
+        offset: span.start.offset + index as u32,
+        line: span.start.line,
+        column: span.start.column + text[..index].chars().count() as u32,
+    };
+    let span_at = |index: usize| {
+        let len = text[index..].chars().next().map_or(0, char::len_utf8);
+        Span::new(position(index), position(index + len))
+    };
+    // Parses the digits between the byte indices.
+    let digits = |start: usize, end: usize, radix: usize| {
+        let digits = parse_digits(&text[start..end], radix).map_err(|index| {
+            ParseError::new(
+                span_at(start + index),
+                ParseErrorKind::InvalidDigit {
+                    digit: text[start + index..].chars().next().unwrap(),
+                    radix,
+                },
+                vec![Expected::Digit { radix }],
+            )
+        })?;
+        if digits.is_empty() {
+            return Err(ParseError::new(
+                span_at(start),
+                ParseErrorKind::MissingDigits,
+                vec![Expected::Digit { radix }],
+            ));
+        }
+        Ok(digits)
+    };
+
+    let is_negative = text.starts_with('-');
+    let start = if is_negative { 1 } else { 0 };
+    let (radix, start) = match text[start..].find('r') {
+        None => (10, start),
+        Some(r) => {
+            let r = start + r;
+            let radix = Int::from_digits(&digits(start, r, 10)?, 10)
+                .to_usize()
+                .filter(|radix| (2..=62).contains(radix))
+                .ok_or_else(|| {
+                    ParseError::new(
+                        Span::new(position(start), position(r)),
+                        ParseErrorKind::InvalidRadix(text[start..r].to_owned()),
+                        vec![],
+                    )
+                })?;
+            (radix, r + 1)
+        }
+    };
+    let slash = text[start..].find('/').map(|slash| start + slash);
+    let numerator_end = slash.unwrap_or(text.len());
+    let dot = text[start..numerator_end].find('.').map(|dot| start + dot);
+
+    let mut numerator = digits(start, dot.unwrap_or(numerator_end), radix)?;
+    let decimal_places = match dot {
+        Some(dot) => {
+            let decimals = digits(dot + 1, numerator_end, radix)?;
+            numerator.extend(&decimals);
+            decimals.len()
+        }
+        None => 0,
+    };
+    let mut numerator = Int::from_digits(&numerator, radix as u32);
+    if is_negative {
+        numerator = -numerator;
+    }
+    let denominator = match slash {
+        Some(slash) => {
+            let denominator =
+                Int::from_digits(&digits(slash + 1, text.len(), radix)?, radix as u32);
+            if denominator.is_zero() {
+                return Err(ParseError::new(
+                    Span::new(position(slash + 1), span.end),
+                    ParseErrorKind::ZeroDenominator,
+                    vec![],
+                ));
+            }
+            Some(denominator)
+        }
+        None => None,
+    };
+    if dot.is_none() && denominator.is_none() {
+        return Ok(Number::Int(numerator));
+    }
+
+    let scale = num_traits::pow(BigInt::from(radix), decimal_places);
+    let denominator = denominator.map_or(BigInt::one(), |it| it.to_big()) * scale;
+    Ok(Rational::new(numerator.to_big(), denominator).into())
+}
+
+/// Parses the text of a number literal like `-1_000`, `16rff` or `1/3`.
+/// Returns `None` if it's not exactly one number.
+pub fn parse_number(text: &str) -> Option<Number> {
+    lower_number(text, Span::default()).ok()
+}
+
+/// Turns digit chars into their values in the given radix, skipping
+/// underscores between two digits. If there's an invalid digit or an
+/// underscore somewhere else, returns its byte index.
+fn parse_digits(digits: &str, radix: usize) -> Result<Vec<u8>, usize> {
+    const DIGITS: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut values = vec![];
+    let mut previous = None;
+    let mut chars = digits.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c == '_' {
+            let next = chars.peek().map(|(_, next)| *next);
+            if matches!(previous, None | Some('_')) || matches!(next, None | Some('_')) {
+                return Err(index);
+            }
+        } else {
+            let digit = DIGITS[..radix].find(c).ok_or(index)?;
+            values.push(digit as u8);
+        }
+        previous = Some(c);
+    }
+    Ok(values)
+}
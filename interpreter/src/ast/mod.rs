@@ -0,0 +1,249 @@
+mod cst;
+mod error;
+mod formatter;
+mod lower;
+mod parse;
+mod span;
+
+pub use cst::*;
+pub use error::*;
+pub use formatter::format_source;
+pub use lower::parse_number;
+pub use parse::MAX_NESTING;
+pub use span::*;
+
+use crate::number::Number;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// A node of Mehl code together with the place in the source it came from.
+///
+/// Spans are ignored when comparing or hashing ASTs, so the same code written
+/// at different places is still considered equal.
+#[derive(Debug, Clone)]
+pub struct Ast {
+    pub kind: AstKind,
+    pub span: Span,
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AstKind {
+    Number(Number),
+    String(String),
+    /// A string with code in it, like `"Hello {name}!"`.
+    InterpolatedString(Vec<StringPart>),
+    Symbol(String),
+    /// The keys and values of a map in the order they appear in the source.
+    Map(Vec<(Asts, Asts)>),
+    List(Vec<Asts>),
+    Code(Asts),
+    Name(String),
+    /// Code that couldn't be parsed. The parser reports an error for it.
+    Error,
+}
+pub type Asts = Vec<Ast>;
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum StringPart {
+    Text(String),
+    Code(Asts),
+}
+
+impl Ast {
+    pub fn new(kind: AstKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+/// Returns the span covering all the given ASTs, for example the ones making up
+/// a list item or a map key. Returns `None` if there are no ASTs.
+pub fn span_of(asts: &[Ast]) -> Option<Span> {
+    let first = asts.first()?.span;
+    let last = asts.last()?.span;
+    Some(first.to(last))
+}
+
+/// Finds the innermost AST containing the given byte offset, which is useful
+/// for editor features like hovering.
+pub fn ast_at(asts: &[Ast], offset: usize) -> Option<&Ast> {
+    let ast = asts.iter().find(|ast| ast.span.contains(offset))?;
+    let inner = match &ast.kind {
+        AstKind::Map(map) => map
+            .iter()
+            .find_map(|(key, value)| ast_at(key, offset).or_else(|| ast_at(value, offset))),
+        AstKind::List(list) => list.iter().find_map(|item| ast_at(item, offset)),
+        AstKind::Code(code) => ast_at(code, offset),
+        AstKind::InterpolatedString(parts) => parts.iter().find_map(|part| match part {
+            StringPart::Text(_) => None,
+            StringPart::Code(code) => ast_at(code, offset),
+        }),
+        _ => None,
+    };
+    Some(inner.unwrap_or(ast))
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+impl fmt::Display for AstKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AstKind::Number(number) => write!(f, "{}", number),
+            AstKind::String(string) => write!(f, "\"{}\"", escape(string)),
+            AstKind::InterpolatedString(parts) => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match part {
+                        StringPart::Text(text) => write!(f, "{}", escape(text))?,
+                        StringPart::Code(code) => write!(f, "{{{}}}", format_code(code))?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            AstKind::Symbol(symbol) => write!(f, ":{}", symbol),
+            AstKind::Map(map) => write!(
+                f,
+                "{{{}}}",
+                itertools::join(
+                    map.iter().map(|(key, value)| format!(
+                        "{}, {}",
+                        format_code(key),
+                        &format_code(value)
+                    )),
+                    ", "
+                )
+            ),
+            AstKind::List(list) => write!(
+                f,
+                "({})",
+                itertools::join(list.iter().map(|item| format_code(item)), ", ")
+            ),
+            AstKind::Code(code) => write!(
+                f,
+                "[{}]",
+                itertools::join(code.iter().map(|item| format!("{}", item)), " ")
+            ),
+            AstKind::Name(name) => write!(f, "{}", name),
+            AstKind::Error => write!(f, "<error>"),
+        }
+    }
+}
+
+/// Escapes the text of a string so that it can be put between double quotes.
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '{' | '}' | '"' | '\\' => format!("\\{}", c),
+            '\n' => "\\n".to_string(),
+            '\r' => "\\r".to_string(),
+            '\t' => "\\t".to_string(),
+            '\0' => "\\0".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+pub fn format_code(asts: &[Ast]) -> String {
+    itertools::join(asts.iter().map(|ast| format!("{}", ast)), " ")
+}
+
+impl PartialEq for Ast {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+impl Eq for Ast {}
+impl Hash for Ast {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state)
+    }
+}
+impl Hash for AstKind {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            AstKind::Number(number) => number.hash(state),
+            AstKind::String(string) => string.hash(state),
+            AstKind::InterpolatedString(parts) => parts.hash(state),
+            AstKind::Symbol(symbol) => symbol.hash(state),
+            AstKind::Map(map) => map.hash(state),
+            AstKind::List(list) => list.hash(state),
+            AstKind::Code(code) => code.hash(state),
+            AstKind::Name(name) => name.hash(state),
+            AstKind::Error => {}
+        }
+    }
+}
+#[allow(clippy::wrong_self_convention)]
+impl AstKind {
+    pub fn unit() -> Self {
+        Self::Symbol("".into())
+    }
+    pub fn as_number(self) -> Option<Number> {
+        match self {
+            Self::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+    pub fn as_string(self) -> Option<String> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+    pub fn as_symbol(self) -> Option<String> {
+        match self {
+            Self::Symbol(symbol) => Some(symbol),
+            _ => None,
+        }
+    }
+    pub fn as_map(self) -> Option<Vec<(Asts, Asts)>> {
+        match self {
+            Self::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+    pub fn as_list(self) -> Option<Vec<Asts>> {
+        match self {
+            Self::List(list) => Some(list),
+            _ => None,
+        }
+    }
+    pub fn as_code(self) -> Option<Asts> {
+        match self {
+            Self::Code(code) => Some(code),
+            _ => None,
+        }
+    }
+    pub fn as_name(self) -> Option<String> {
+        match self {
+            Self::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Ast {
+    /// Parses code, recovering from errors. Returns all ASTs that could be
+    /// parsed as well as every error in the code.
+    pub fn parse(input: &str) -> (Asts, Vec<ParseError>) {
+        let (csts, mut errors) = Cst::parse(input);
+        let (asts, lowering_errors) = lower::lower(&csts);
+        errors.extend(lowering_errors);
+        errors.sort_by_key(|error| error.span.start);
+        (asts, errors)
+    }
+
+    pub fn parse_all(input: &str) -> Result<Asts, Vec<ParseError>> {
+        match Self::parse(input) {
+            (asts, errors) if errors.is_empty() => Ok(asts),
+            (_, errors) => Err(errors),
+        }
+    }
+}
+
+impl Cst {
+    /// Parses code into a lossless concrete syntax tree, recovering from errors.
+    pub fn parse(input: &str) -> (Vec<Cst>, Vec<ParseError>) {
+        parse::Parser::new(input).parse()
+    }
+}
@@ -0,0 +1,430 @@
+use super::*;
+use nom::FindSubstring;
+use std::cell::RefCell;
+
+/// Either `None` if a parser is not applicable to the input, or the parsed
+/// value together with the remaining input.
+type ParseResult<'a, T> = Option<(T, &'a str)>;
+
+/// Parses Mehl code into CSTs. All inputs that the parser functions receive
+/// are suffixes of the `source`, so the position of some input in the source
+/// is known by comparing their lengths.
+///
+/// The parser never gives up: When it encounters invalid code, it records an
+/// error, skips to the next separator or closing bracket and inserts an error
+/// node if necessary. That way, all problems of a file are reported at once.
+pub struct Parser<'a> {
+    source: &'a str,
+    lines: LineIndex<'a>,
+    errors: RefCell<Vec<ParseError>>,
+    /// The brackets that are currently open, innermost last.
+    open_brackets: RefCell<Vec<char>>,
+}
+
+/// How many brackets can be open at the same time. Deeper code is skipped, so
+/// the recursive functions that handle CSTs and ASTs can't overflow the stack.
+pub const MAX_NESTING: usize = 256;
+
+/// Marks an interpolation in a string in the open brackets. Interpolations are
+/// closed by a brace like maps, but commas don't separate items in them.
+const INTERPOLATION: char = '"';
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | ',')
+}
+fn skip_to_separator(input: &str) -> &str {
+    input.trim_start_matches(|c| !is_separator(c))
+}
+fn closing_bracket(opening: char) -> char {
+    match opening {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            lines: LineIndex::new(source),
+            errors: RefCell::new(vec![]),
+            open_brackets: RefCell::new(vec![]),
+        }
+    }
+
+    fn offset(&self, input: &str) -> usize {
+        self.source.len() - input.len()
+    }
+    fn span(&self, start: &str, end: &str) -> Span {
+        self.lines.span(self.offset(start), self.offset(end))
+    }
+    /// The span of the next char of the input, or an empty span at the end.
+    fn char_span(&self, input: &str) -> Span {
+        let len = input.chars().next().map(char::len_utf8).unwrap_or(0);
+        self.span(input, &input[len..])
+    }
+    /// The text between the start and the end input.
+    fn text(&self, start: &'a str, end: &'a str) -> String {
+        start[..start.len() - end.len()].to_owned()
+    }
+
+    fn error(&self, span: Span, kind: ParseErrorKind, expected: Vec<Expected>) {
+        self.errors
+            .borrow_mut()
+            .push(ParseError::new(span, kind, expected));
+    }
+
+    /// Runs the given parser while the bracket is open.
+    fn in_brackets<T>(&self, bracket: char, parser: impl FnOnce() -> T) -> T {
+        self.open_brackets.borrow_mut().push(bracket);
+        let result = parser();
+        self.open_brackets.borrow_mut().pop();
+        result
+    }
+    fn closes_open_bracket(&self, c: char) -> bool {
+        self.open_brackets
+            .borrow()
+            .iter()
+            .any(|bracket| closing_bracket(*bracket) == c)
+    }
+    /// Skips code up to a closing bracket of one of the open brackets,
+    /// including brackets opened and closed in between.
+    fn skip_nested(&self, input: &'a str) -> &'a str {
+        let mut depth = 0usize;
+        for (index, c) in input.char_indices() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                _ if depth == 0 && self.closes_open_bracket(c) => return &input[index..],
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        ""
+    }
+    fn separates_items(&self) -> bool {
+        matches!(self.open_brackets.borrow().last(), Some('(' | '{'))
+    }
+
+    fn whitespace(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let rest = input.trim_start();
+        if rest.len() == input.len() {
+            return None;
+        }
+        Some((CstKind::Whitespace(self.text(input, rest)), rest))
+    }
+
+    fn comment(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        if !input.starts_with('#') {
+            return None;
+        }
+        let end_of_line = input.find('\n').unwrap_or(input.len());
+        let rest = &input[end_of_line..];
+        Some((CstKind::Comment(self.text(input, rest)), rest))
+    }
+
+    /// Parses a number like `123`, `-1_000`, `2r100100101`, `36rax9z3l1m6`,
+    /// `3.14`, or `1/3`. The digits are only checked when lowering the CST.
+    fn number(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let unsigned = input.strip_prefix('-').unwrap_or(input);
+        if !unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let rest = skip_to_separator(input);
+        Some((CstKind::Number(self.text(input, rest)), rest))
+    }
+
+    /// Parses a string like `"Foo"`, `"Hi, {name}!\n"`, or
+    /// `'"Foo's label said: "Foo""'`. Strings without single quotes may contain
+    /// escape sequences and interpolations in braces. Strings with single
+    /// quotes are raw: They end at a double quote followed by the same number
+    /// of single quotes, and everything in between is taken literally.
+    fn string(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let start = input;
+        let single_quotes = input.chars().take_while(|c| *c == '\'').count();
+        let input = &input[single_quotes..];
+        if !input.starts_with('"') {
+            if single_quotes == 0 {
+                return None;
+            }
+            self.error(
+                self.char_span(input),
+                ParseErrorKind::MissingDoubleQuote,
+                vec![Expected::Text("\"".into())],
+            );
+            let rest = skip_to_separator(input);
+            return Some((CstKind::Error(self.text(start, rest)), rest));
+        }
+        let input = &input[1..];
+
+        let ending_sequence = std::iter::once('"')
+            .chain(itertools::repeat_n('\'', single_quotes))
+            .collect::<String>();
+        let (parts, rest, is_terminated) = if single_quotes == 0 {
+            self.string_parts(input)
+        } else {
+            let end = input.find_substring(&ending_sequence);
+            let content_end = end.map_or("", |end| &input[end..]);
+            let rest = end.map_or("", |end| &input[end + ending_sequence.len()..]);
+            let text = Cst::new(
+                CstKind::StringText(self.text(input, content_end)),
+                self.span(input, content_end),
+            );
+            (vec![text], rest, end.is_some())
+        };
+        if !is_terminated {
+            self.error(
+                self.span(start, rest),
+                ParseErrorKind::UnterminatedString,
+                vec![Expected::Text(ending_sequence)],
+            );
+        }
+        Some((
+            CstKind::String {
+                single_quotes,
+                parts,
+                is_terminated,
+            },
+            rest,
+        ))
+    }
+
+    /// Parses the parts of a string without single quotes, up to and including
+    /// the closing double quote. Returns whether that quote was found.
+    fn string_parts(&self, input: &'a str) -> (Vec<Cst>, &'a str, bool) {
+        let mut parts = vec![];
+        let mut input = input;
+        let mut text_start = input;
+        let flush_text = |parts: &mut Vec<Cst>, text_start: &'a str, input: &'a str| {
+            if text_start.len() > input.len() {
+                parts.push(Cst::new(
+                    CstKind::StringText(self.text(text_start, input)),
+                    self.span(text_start, input),
+                ));
+            }
+        };
+        loop {
+            let c = match input.chars().next() {
+                Some(c) => c,
+                None => {
+                    flush_text(&mut parts, text_start, input);
+                    return (parts, input, false);
+                }
+            };
+            match c {
+                '"' => {
+                    flush_text(&mut parts, text_start, input);
+                    return (parts, &input[1..], true);
+                }
+                '\\' => {
+                    // Escape sequences are only checked when lowering the CST.
+                    // Here, it's only important that escaped quotes and braces
+                    // don't end the string or start an interpolation.
+                    let rest = &input[1..];
+                    let escaped_len = rest.chars().next().map_or(0, char::len_utf8);
+                    let rest = &rest[escaped_len..];
+                    input = match (input[1..].starts_with('u'), rest.strip_prefix('{')) {
+                        (true, Some(code)) => {
+                            let code_len = code.find(['}', '"']).unwrap_or(code.len());
+                            let code = &code[code_len..];
+                            code.strip_prefix('}').unwrap_or(code)
+                        }
+                        _ => rest,
+                    };
+                }
+                '{' => {
+                    flush_text(&mut parts, text_start, input);
+                    let opening = input;
+                    let (children, rest) =
+                        self.in_brackets(INTERPOLATION, || self.csts(&input[1..]));
+                    let (rest, is_closed) = match rest.strip_prefix('}') {
+                        Some(rest) => (rest, true),
+                        None => {
+                            self.error(
+                                self.char_span(rest),
+                                ParseErrorKind::Unclosed {
+                                    bracket: '{',
+                                    opened: self.lines.position(self.offset(opening)),
+                                },
+                                vec![Expected::Text("}".into())],
+                            );
+                            (rest, false)
+                        }
+                    };
+                    parts.push(Cst::new(
+                        CstKind::Interpolation {
+                            children,
+                            is_closed,
+                        },
+                        self.span(opening, rest),
+                    ));
+                    input = rest;
+                    text_start = input;
+                }
+                _ => input = &input[c.len_utf8()..],
+            }
+        }
+    }
+
+    fn identifier(&self, input: &'a str) -> ParseResult<'a, String> {
+        let rest = input.trim_start_matches(is_valid_identifier_char);
+        if rest.len() == input.len() {
+            return None;
+        }
+        Some((self.text(input, rest), rest))
+    }
+
+    fn symbol(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let input = input.strip_prefix(':')?;
+        let (symbol, rest) = self.identifier(input).unwrap_or_else(|| ("".into(), input));
+        Some((CstKind::Symbol(symbol), rest))
+    }
+
+    fn name(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let (name, rest) = self.identifier(input)?;
+        Some((CstKind::Name(name), rest))
+    }
+
+    /// Parses the children between brackets. If the bracket is never closed,
+    /// the children so far are returned.
+    fn bracketed(&self, opening: char, input: &'a str) -> ParseResult<'a, (Vec<Cst>, bool)> {
+        let start = input;
+        let input = input.strip_prefix(opening)?;
+        let closing = closing_bracket(opening);
+        let (children, rest) = self.in_brackets(opening, || self.csts(input));
+        match rest.strip_prefix(closing) {
+            Some(rest) => Some(((children, true), rest)),
+            None => {
+                self.error(
+                    self.char_span(rest),
+                    ParseErrorKind::Unclosed {
+                        bracket: opening,
+                        opened: self.lines.position(self.offset(start)),
+                    },
+                    vec![Expected::Text(closing.to_string())],
+                );
+                Some(((children, false), rest))
+            }
+        }
+    }
+
+    fn list(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let ((children, is_closed), rest) = self.bracketed('(', input)?;
+        Some((
+            CstKind::List {
+                children,
+                is_closed,
+            },
+            rest,
+        ))
+    }
+
+    fn map(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let ((children, is_closed), rest) = self.bracketed('{', input)?;
+        Some((
+            CstKind::Map {
+                children,
+                is_closed,
+            },
+            rest,
+        ))
+    }
+
+    fn code(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        let ((children, is_closed), rest) = self.bracketed('[', input)?;
+        Some((
+            CstKind::Code {
+                children,
+                is_closed,
+            },
+            rest,
+        ))
+    }
+
+    fn comma(&self, input: &'a str) -> ParseResult<'a, CstKind> {
+        if !self.separates_items() {
+            return None;
+        }
+        Some((CstKind::Comma, input.strip_prefix(',')?))
+    }
+
+    fn cst(&self, input: &'a str) -> ParseResult<'a, Cst> {
+        let parsers: Vec<fn(&Self, &'a str) -> ParseResult<'a, CstKind>> = vec![
+            Self::whitespace,
+            Self::comment,
+            Self::comma,
+            Self::number,
+            Self::string,
+            Self::symbol,
+            Self::list,
+            Self::map,
+            Self::code,
+            Self::name,
+        ];
+        parsers.into_iter().find_map(|parser| {
+            let (kind, rest) = parser(self, input)?;
+            Some((Cst::new(kind, self.span(input, rest)), rest))
+        })
+    }
+
+    /// Parses CSTs until the end of the input or a closing bracket of one of
+    /// the open brackets. Stray closing brackets and commas are reported and
+    /// turned into error nodes, and so is code nested deeper than
+    /// [`MAX_NESTING`].
+    fn csts(&self, input: &'a str) -> (Vec<Cst>, &'a str) {
+        if self.open_brackets.borrow().len() > MAX_NESTING {
+            // All opening brackets are one byte long.
+            let bracket = self.offset(input) - 1;
+            self.error(
+                self.lines.span(bracket, bracket + 1),
+                ParseErrorKind::NestedTooDeeply,
+                vec![],
+            );
+            let rest = self.skip_nested(input);
+            let skipped = Cst::new(
+                CstKind::Error(self.text(input, rest)),
+                self.span(input, rest),
+            );
+            return (vec![skipped], rest);
+        }
+        let mut input = input;
+        let mut csts = vec![];
+        loop {
+            if let Some((cst, rest)) = self.cst(input) {
+                csts.push(cst);
+                input = rest;
+                continue;
+            }
+            match input.chars().next() {
+                None => break,
+                Some(c) if self.closes_open_bracket(c) => break,
+                Some(c) => {
+                    let span = self.char_span(input);
+                    self.error(
+                        span,
+                        ParseErrorKind::UnexpectedCharacter(c),
+                        vec![Expected::Ast],
+                    );
+                    csts.push(Cst::new(CstKind::Error(c.to_string()), span));
+                    input = &input[c.len_utf8()..];
+                }
+            }
+        }
+        (csts, input)
+    }
+
+    /// Parses the whole source and returns the CSTs together with all errors.
+    /// Sources that are too long for spans aren't parsed at all.
+    pub fn parse(self) -> (Vec<Cst>, Vec<ParseError>) {
+        if self.source.len() > MAX_SOURCE_LEN {
+            let error = ParseError::new(Span::default(), ParseErrorKind::SourceTooLong, vec![]);
+            return (vec![], vec![error]);
+        }
+        let (csts, _) = self.csts(self.source);
+        (csts, self.errors.into_inner())
+    }
+}
+
+fn is_valid_identifier_char(c: char) -> bool {
+    !c.is_whitespace() && "[]{}(),:".chars().all(|it| it != c)
+}
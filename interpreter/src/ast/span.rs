@@ -0,0 +1,118 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A position in a source file. Lines and columns start at zero, and the column
+/// counts chars rather than bytes. The fields are `u32`s to keep ASTs small.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    pub offset: u32,
+    pub line: u32,
+    pub column: u32,
+}
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+/// A range in a source file, from `start` (inclusive) to `end` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the smallest span that covers both this span and the other one.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        (self.start.offset as usize) <= offset && offset < self.end.offset as usize
+    }
+}
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// The length in bytes of the longest source that positions can point into.
+pub const MAX_SOURCE_LEN: usize = u32::MAX as usize;
+
+/// Turns byte offsets into line and column positions. The source can't be
+/// longer than [`MAX_SOURCE_LEN`].
+///
+/// Columns count chars, so the index remembers how many chars come before
+/// every [`CHECKPOINT_DISTANCE`]th byte. Lookups only count the chars after the
+/// nearest checkpoint, which keeps them fast even on very long lines.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    chars_before_checkpoints: Vec<usize>,
+}
+const CHECKPOINT_DISTANCE: usize = 64;
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        let chars_before_checkpoints = std::iter::once(0)
+            .chain(
+                source
+                    .as_bytes()
+                    .chunks(CHECKPOINT_DISTANCE)
+                    .map(count_chars)
+                    .scan(0, |chars, count| {
+                        *chars += count;
+                        Some(*chars)
+                    }),
+            )
+            .collect();
+        Self {
+            source,
+            line_starts,
+            chars_before_checkpoints,
+        }
+    }
+
+    /// The number of chars before the byte offset.
+    fn chars_before(&self, offset: usize) -> usize {
+        let checkpoint = offset / CHECKPOINT_DISTANCE;
+        let bytes = &self.source.as_bytes()[checkpoint * CHECKPOINT_DISTANCE..offset];
+        self.chars_before_checkpoints[checkpoint] + count_chars(bytes)
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        let column = self.chars_before(offset) - self.chars_before(self.line_starts[line]);
+        let to_u32 =
+            |value: usize| u32::try_from(value).expect("The source is longer than MAX_SOURCE_LEN.");
+        Position {
+            offset: to_u32(offset),
+            line: to_u32(line),
+            column: to_u32(column),
+        }
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(self.position(start), self.position(end))
+    }
+}
+
+/// Counts the chars that start in some UTF-8 bytes, which don't have to begin
+/// or end at char boundaries.
+fn count_chars(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|byte| (**byte & 0xC0) != 0x80).count()
+}
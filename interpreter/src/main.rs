@@ -1,13 +1,14 @@
-mod ast;
-mod runner;
-
-use ast::*;
-use clap::{App, SubCommand};
+use clap::{App, Arg, SubCommand};
 use colored::Colorize;
+use itertools::Itertools;
 use lspower::jsonrpc::Result;
 use lspower::lsp::*;
 use lspower::{Client, LanguageServer, LspService, Server};
+use mehl::ast::*;
+use mehl::runner;
 use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
 #[tokio::main]
 async fn main() {
@@ -23,64 +24,125 @@ async fn main() {
         .version("0.0.0")
         .author("Marcel Garus <marcel.garus@gmail.com>")
         .about("Mehl language utility")
-        .subcommand(SubCommand::with_name("run").about("Runs a Mehl file."))
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs a Mehl file.")
+                .arg(
+                    Arg::with_name("max-depth")
+                        .long("max-depth")
+                        .takes_value(true)
+                        .help("The maximum number of nested calls before the program panics."),
+                )
+                .arg(
+                    Arg::with_name("virtual-time")
+                        .long("virtual-time")
+                        .help("Simulates time, so waiting finishes instantly."),
+                )
+                .arg(
+                    Arg::with_name("seed").long("seed").takes_value(true).help(
+                        "Makes the order of fibers and random numbers only depend on the seed.",
+                    ),
+                )
+                .arg(capability_arg("allow-read").help(
+                    "Allows reading these files and directories, or all of them if none are given.",
+                ))
+                .arg(capability_arg("allow-write").help(
+                    "Allows writing these files and directories, or all of them if none are given.",
+                ))
+                .arg(
+                    capability_arg("allow-run")
+                        .help("Allows running these programs, or all of them if none are given."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats Mehl files.")
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Only checks whether the files are formatted."),
+                )
+                .arg(Arg::with_name("files").multiple(true).required(true)),
+        )
         .subcommand(SubCommand::with_name("lsp"))
         .get_matches();
 
-    if let Some(_) = matches.subcommand_matches("run") {
-        println!("Running test.mehl.");
-        let core = {
-            let code = std::fs::read_to_string("core.mehl").expect("File core.mehl not found.");
-            match Ast::parse_all(&code) {
-                Ok(it) => it,
-                Err(err) => panic!("Couldn't parse ASTs of core.mehl: {}", err),
+    if let Some(matches) = matches.subcommand_matches("run") {
+        let max_depth = match matches.value_of("max-depth").map(str::parse) {
+            None => runner::Runtime::DEFAULT_MAX_DEPTH,
+            Some(Ok(max_depth)) => max_depth,
+            Some(Err(_)) => {
+                println!("{}", "--max-depth needs to be a positive integer.".red());
+                std::process::exit(1);
             }
         };
-        println!("Core parsed.");
-        let user = {
-            let code = std::fs::read_to_string("test.mehl").expect("File test.mehl not found.");
-            match Ast::parse_all(&code) {
-                Ok(it) => it,
-                Err(err) => panic!("Couldn't parse ASTs of test.mehl: {}", err),
+        let seed = match matches.value_of("seed").map(str::parse) {
+            None => None,
+            Some(Ok(seed)) => Some(seed),
+            Some(Err(_)) => {
+                println!("{}", "--seed needs to be a positive integer.".red());
+                std::process::exit(1);
             }
         };
-        println!("Test parsed.");
-
-        println!("Code: {}", format_code(&user));
-        let mut fiber = runner::Runtime::default();
-        let context = runner::Context::root(&mut fiber);
-        let context = match context.run(&mut fiber, core) {
-            Ok(context) => context,
-            Err(err) => panic!("The core library panicked: {}", err),
-        };
-        let context = match context.run(&mut fiber, user) {
-            Ok(context) => context,
+        let virtual_time = matches.is_present("virtual-time");
+        let capabilities = match capabilities(matches) {
+            Ok(capabilities) => capabilities,
             Err(err) => {
-                println!(
-                    "{}\n{}{}",
-                    "The program panicked.".red(),
-                    "Message: ".red(),
-                    err.to_string().bright_red().bold()
-                );
-                return;
+                println!("{}", format!("Couldn't grant capabilities: {}", err).red());
+                std::process::exit(1);
             }
         };
+        // Calls of Mehl code live on the heap, but guards run nested on the
+        // native stack, so the stack has to be big enough for the most deeply
+        // nested guards.
+        let stack_size =
+            (runner::Runtime::MAX_NESTED_RUNS as usize) * STACK_SIZE_PER_NESTED_RUN + (8 << 20);
+        std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || run(max_depth, virtual_time, seed, capabilities))
+            .expect("Couldn't start the runner.")
+            .join()
+            .expect("The runner crashed.");
+    }
 
-        let output = context.dot;
-        println!(
-            "{}\n{}{}",
-            "The program successfully finished.".green(),
-            "Output: ".green(),
-            output.to_string().bright_green().bold(),
-        );
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let check = matches.is_present("check");
+        let mut is_ok = true;
+        for file in matches.values_of("files").unwrap() {
+            let source = read_file(file);
+            match format_source(&source) {
+                Ok(formatted) if formatted == source => {}
+                Ok(_) if check => {
+                    println!("{}", format!("{} is not formatted.", file).red());
+                    is_ok = false;
+                }
+                Ok(formatted) => {
+                    std::fs::write(file, formatted)
+                        .unwrap_or_else(|_| panic!("Couldn't write to {}.", file));
+                    println!("Formatted {}.", file);
+                }
+                Err(errors) => {
+                    for error in errors {
+                        println!("{}\n", error.in_file(file).render(&source));
+                    }
+                    is_ok = false;
+                }
+            }
+        }
+        if !is_ok {
+            std::process::exit(1);
+        }
     }
 
-    if let Some(_) = matches.subcommand_matches("lsp") {
+    if matches.subcommand_matches("lsp").is_some() {
         // println!("Running Mehl LSP. 🍞");
         let stdin = tokio::io::stdin();
         let stdout = tokio::io::stdout();
 
-        let (service, messages) = LspService::new(|client| Backend { client });
+        let (service, messages) = LspService::new(|client| Backend {
+            client,
+            documents: Mutex::new(HashMap::new()),
+        });
         Server::new(stdin, stdout)
             .interleave(messages)
             .serve(service)
@@ -88,9 +150,154 @@ async fn main() {
     }
 }
 
+/// An argument like `--allow-read=./data,./more` that grants a capability. If
+/// it's given without values, it grants everything.
+fn capability_arg(name: &str) -> Arg<'_, '_> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .min_values(0)
+        .multiple(true)
+        .require_equals(true)
+        .use_delimiter(true)
+}
+fn capabilities(matches: &clap::ArgMatches) -> std::io::Result<runner::Capabilities> {
+    let granted = |name| {
+        matches
+            .is_present(name)
+            .then(|| matches.values_of(name).into_iter().flatten().collect_vec())
+    };
+    let mut capabilities = runner::Capabilities::default();
+    match granted("allow-read") {
+        Some(paths) if paths.is_empty() => capabilities = capabilities.allow_read_all(),
+        Some(paths) => {
+            for path in paths {
+                capabilities = capabilities.allow_read(path)?;
+            }
+        }
+        None => {}
+    }
+    match granted("allow-write") {
+        Some(paths) if paths.is_empty() => capabilities = capabilities.allow_write_all(),
+        Some(paths) => {
+            for path in paths {
+                capabilities = capabilities.allow_write(path)?;
+            }
+        }
+        None => {}
+    }
+    match granted("allow-run") {
+        Some(programs) if programs.is_empty() => capabilities = capabilities.allow_run_all(),
+        Some(programs) => {
+            for program in programs {
+                capabilities = capabilities.allow_run(program);
+            }
+        }
+        None => {}
+    }
+    Ok(capabilities)
+}
+
+fn read_file(file: &str) -> String {
+    std::fs::read_to_string(file).unwrap_or_else(|_| panic!("File {} not found.", file))
+}
+
+/// The native stack space that each level of nested guards needs.
+const STACK_SIZE_PER_NESTED_RUN: usize = 64 << 10;
+
+fn run(max_depth: u64, virtual_time: bool, seed: Option<u64>, capabilities: runner::Capabilities) {
+    println!("Running test.mehl.");
+    let core = match parse_file("core.mehl") {
+        Some(it) => it,
+        None => return,
+    };
+    println!("Core parsed.");
+    let user = match parse_file("test.mehl") {
+        Some(it) => it,
+        None => return,
+    };
+    println!("Test parsed.");
+
+    println!("Code: {}", format_code(&user));
+    let mut host = runner::ProcessHost::default();
+    if virtual_time {
+        host = host.with_clock(runner::VirtualClock::default());
+    }
+    if let Some(seed) = seed {
+        host = host.with_seed(seed);
+    }
+    let mut fiber = runner::Runtime::default()
+        .with_max_depth(max_depth)
+        .with_host(host)
+        .with_capabilities(capabilities);
+    if let Some(seed) = seed {
+        fiber = fiber.with_seed(seed);
+    }
+    let context = runner::Context::root(&mut fiber).in_source("core.mehl");
+    let context = match context.run(&mut fiber, runner::Chunk::compile(core)) {
+        Ok(context) => context,
+        Err(err) => panic!("The core library panicked: {}", err),
+    };
+    let context = match context
+        .in_source("test.mehl")
+        .run(&mut fiber, runner::Chunk::compile(user))
+    {
+        Ok(context) => context,
+        Err(err) => {
+            println!("{}", "Traceback (most recent call last):".red());
+            let trace = fiber.take_trace();
+            let calls = trace
+                .iter()
+                .rev()
+                .group_by(|frame| (&frame.source, frame.span, &frame.name));
+            for (_, frames) in &calls {
+                let frames = frames.collect::<Vec<_>>();
+                for frame in frames.iter().take(3) {
+                    println!("  {}", frame.to_string().red());
+                }
+                let calls = frames.iter().map(|frame| 1 + frame.repeated).sum::<u64>();
+                let shown = frames.len().min(3) as u64;
+                if calls > shown {
+                    let repeated = format!("[Previous line repeated {} more times]", calls - shown);
+                    println!("  {}", repeated.red());
+                }
+            }
+            println!(
+                "{}\n{}{}",
+                "The program panicked.".red(),
+                "Message: ".red(),
+                err.to_string().bright_red().bold()
+            );
+            return;
+        }
+    };
+
+    let output = context.dot;
+    println!(
+        "{}\n{}{}",
+        "The program successfully finished.".green(),
+        "Output: ".green(),
+        output.to_string().bright_green().bold(),
+    );
+}
+
+fn parse_file(file: &str) -> Option<Asts> {
+    let code = read_file(file);
+    match Ast::parse_all(&code) {
+        Ok(asts) => Some(asts),
+        Err(errors) => {
+            for error in errors {
+                println!("{}\n", error.in_file(file).render(&code));
+            }
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Backend {
     client: Client,
+    documents: Mutex<HashMap<Url, String>>,
 }
 
 #[lspower::async_trait]
@@ -100,7 +307,16 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::Info, "Initializing!")
             .await;
-        Ok(InitializeResult::default())
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::Full,
+                )),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                ..ServerCapabilities::default()
+            },
+            ..InitializeResult::default()
+        })
     }
 
     async fn initialized(&self, _: InitializedParams) {
@@ -112,4 +328,57 @@ impl LanguageServer for Backend {
     async fn shutdown(&self) -> Result<()> {
         Ok(())
     }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        self.publish_diagnostics(params.text_document.uri, &params.text_document.text)
+            .await;
+    }
+
+    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
+        if let Some(change) = params.content_changes.pop() {
+            self.publish_diagnostics(params.text_document.uri, &change.text)
+                .await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.documents
+            .lock()
+            .unwrap()
+            .remove(&params.text_document.uri);
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let documents = self.documents.lock().unwrap();
+        let source = match documents.get(&params.text_document.uri) {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+        let formatted = match format_source(source) {
+            Ok(formatted) => formatted,
+            Err(_) => return Ok(None),
+        };
+        let whole_document = LineIndex::new(source).span(0, source.len());
+        Ok(Some(vec![TextEdit {
+            range: span_to_range(source, whole_document),
+            new_text: formatted,
+        }]))
+    }
+}
+
+impl Backend {
+    async fn publish_diagnostics(&self, uri: Url, text: &str) {
+        self.documents
+            .lock()
+            .unwrap()
+            .insert(uri.clone(), text.to_string());
+        let (_, errors) = Ast::parse(text);
+        let diagnostics = errors
+            .iter()
+            .map(|error| error.to_diagnostic(text))
+            .collect();
+        self.client
+            .publish_diagnostics(uri, diagnostics, None)
+            .await;
+    }
 }
@@ -0,0 +1,423 @@
+use num_bigint::{BigInt, BigUint};
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+/// A Mehl number. Numbers form a tower: Integers are also rationals, and
+/// rationals can be approximated by floats. When two numbers are combined,
+/// the less general one is promoted first, so an integer plus a rational is a
+/// rational and anything involving a float is a float. Rationals that are
+/// integers are always turned into `Int`s, so every exact number has exactly
+/// one representation.
+///
+/// Numbers of different kinds are never equal, so `1` and `1.0` are different
+/// map keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Number {
+    Int(Int),
+    Rational(Rational),
+    Float(Float),
+}
+
+/// An exact fraction of two integers of arbitrary size.
+pub type Rational = num_rational::BigRational;
+
+/// An IEEE double. Unlike `f64`, it implements `Eq`, `Hash` and `Ord` by
+/// comparing the bits, so `NaN` equals itself and can be used as a map key.
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+/// An integer of arbitrary size. Integers that fit into an `i64` are stored
+/// inline and calculated with directly – only if an operation overflows, the
+/// calculation is redone with a heap-allocated `BigInt`.
+///
+/// `Big` is only used for integers that don't fit into an `i64`, so every
+/// integer has exactly one representation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Int {
+    Small(i64),
+    Big(BigInt),
+}
+
+impl Int {
+    /// Parses digit values (not chars) like `[1, 0, 15]` in the given radix,
+    /// most significant digit first. Works for radixes up to 256.
+    pub fn from_digits(digits: &[u8], radix: u32) -> Self {
+        let magnitude = BigUint::from_radix_be(digits, radix).expect("Digits are valid.");
+        BigInt::from(magnitude).into()
+    }
+
+    pub fn to_big(&self) -> BigInt {
+        match self {
+            Int::Small(int) => BigInt::from(*int),
+            Int::Big(int) => int.clone(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Int::Small(0))
+    }
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Int::Small(int) => *int < 0,
+            Int::Big(int) => int.sign() == num_bigint::Sign::Minus,
+        }
+    }
+}
+
+impl From<i64> for Int {
+    fn from(int: i64) -> Self {
+        Int::Small(int)
+    }
+}
+impl From<BigInt> for Int {
+    fn from(int: BigInt) -> Self {
+        match int.to_i64() {
+            Some(int) => Int::Small(int),
+            None => Int::Big(int),
+        }
+    }
+}
+
+impl ToPrimitive for Int {
+    fn to_i64(&self) -> Option<i64> {
+        match self {
+            Int::Small(int) => Some(*int),
+            Int::Big(_) => None,
+        }
+    }
+    fn to_u64(&self) -> Option<u64> {
+        match self {
+            Int::Small(int) => int.to_u64(),
+            Int::Big(int) => int.to_u64(),
+        }
+    }
+    /// Rounds to the nearest float. Integers too big for a float become an
+    /// infinity.
+    fn to_f64(&self) -> Option<f64> {
+        match self {
+            Int::Small(int) => Some(*int as f64),
+            Int::Big(int) => int.to_f64(),
+        }
+    }
+}
+
+/// Implements an operator by trying the checked `i64` operation first and
+/// falling back to `BigInt`s if it overflows.
+macro_rules! impl_op {
+    ($trait:ident, $method:ident, $checked:ident) => {
+        impl $trait for Int {
+            type Output = Int;
+            fn $method(self, other: Int) -> Int {
+                if let (Int::Small(a), Int::Small(b)) = (&self, &other) {
+                    if let Some(result) = a.$checked(*b) {
+                        return Int::Small(result);
+                    }
+                }
+                self.to_big().$method(other.to_big()).into()
+            }
+        }
+    };
+}
+impl_op!(Add, add, checked_add);
+impl_op!(Sub, sub, checked_sub);
+impl_op!(Mul, mul, checked_mul);
+impl_op!(Div, div, checked_div);
+impl_op!(Rem, rem, checked_rem);
+
+impl Neg for Int {
+    type Output = Int;
+    fn neg(self) -> Int {
+        match self {
+            Int::Small(int) => match int.checked_neg() {
+                Some(int) => Int::Small(int),
+                None => (-BigInt::from(int)).into(),
+            },
+            Int::Big(int) => (-int).into(),
+        }
+    }
+}
+
+impl std::iter::Sum for Int {
+    fn sum<I: Iterator<Item = Int>>(iter: I) -> Self {
+        iter.fold(Int::Small(0), Add::add)
+    }
+}
+impl std::iter::Product for Int {
+    fn product<I: Iterator<Item = Int>>(iter: I) -> Self {
+        iter.fold(Int::Small(1), Mul::mul)
+    }
+}
+
+impl Ord for Int {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) => a.cmp(b),
+            _ => self.to_big().cmp(&other.to_big()),
+        }
+    }
+}
+impl PartialOrd for Int {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Int {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Int::Small(int) => write!(f, "{}", int),
+            Int::Big(int) => write!(f, "{}", int),
+        }
+    }
+}
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Float {}
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Finite floats are written out as decimal numbers that always have a
+        // decimal point, so they are not mistaken for integers and parse back
+        // to the same value. Debug formatting would use exponents like `1e40`,
+        // which are no number literals.
+        let float = self.0;
+        if float.is_nan() {
+            write!(f, "nan")
+        } else if float.is_infinite() {
+            write!(f, "{}inf", if float < 0.0 { "-" } else { "" })
+        } else if float.fract() == 0.0 {
+            write!(f, "{}.0", float)
+        } else {
+            write!(f, "{}", float)
+        }
+    }
+}
+
+/// Two numbers promoted to the same kind.
+enum Promoted {
+    Ints(Int, Int),
+    Rationals(Rational, Rational),
+    Floats(f64, f64),
+}
+fn promote(a: Number, b: Number) -> Promoted {
+    match (a, b) {
+        (Number::Int(a), Number::Int(b)) => Promoted::Ints(a, b),
+        (a @ Number::Float(_), b) | (a, b @ Number::Float(_)) => {
+            Promoted::Floats(a.to_f64(), b.to_f64())
+        }
+        (a, b) => Promoted::Rationals(a.to_rational(), b.to_rational()),
+    }
+}
+
+impl Number {
+    fn to_rational(&self) -> Rational {
+        match self {
+            Number::Int(int) => Rational::from_integer(int.to_big()),
+            Number::Rational(rational) => rational.clone(),
+            Number::Float(float) => Rational::from_float(float.0)
+                .expect("Only exact numbers are turned into rationals."),
+        }
+    }
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(int) => int.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(rational) => rational.to_f64().unwrap_or(f64::NAN),
+            Number::Float(float) => float.0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(int) => int.is_zero(),
+            Number::Rational(_) => false,
+            Number::Float(float) => float.0 == 0.0,
+        }
+    }
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Number::Int(int) => int.is_negative(),
+            Number::Rational(rational) => rational.is_negative(),
+            Number::Float(float) => float.0 < 0.0,
+        }
+    }
+
+    /// Compares numbers by their value, so unlike `==`, it considers `1` and
+    /// `1.0` to be equal. Floats are compared using IEEE's total order.
+    /// Exact numbers are compared with floats exactly rather than by turning
+    /// them into floats, which would round them.
+    pub fn compare(&self, other: &Number) -> Ordering {
+        match (self, other) {
+            (Number::Float(a), Number::Float(b)) => a.cmp(b),
+            (exact, Number::Float(float)) => exact.compare_to_float(float.0),
+            (Number::Float(float), exact) => exact.compare_to_float(float.0).reverse(),
+            (a, b) => match promote(a.clone(), b.clone()) {
+                Promoted::Ints(a, b) => a.cmp(&b),
+                Promoted::Rationals(a, b) => a.cmp(&b),
+                Promoted::Floats(..) => unreachable!("Exact numbers don't become floats."),
+            },
+        }
+    }
+    /// Compares an exact number with a float. Like `0.0`, `0` is bigger than
+    /// `-0.0`. Infinities and `NaN`s are bigger or smaller than every exact
+    /// number, depending on their sign.
+    fn compare_to_float(&self, float: f64) -> Ordering {
+        match Rational::from_float(float) {
+            Some(_) if float == 0.0 && float.is_sign_negative() => self
+                .compare(&Number::Int(Int::Small(0)))
+                .then(Ordering::Greater),
+            Some(rational) => self.to_rational().cmp(&rational),
+            None if float.is_sign_negative() => Ordering::Greater,
+            None => Ordering::Less,
+        }
+    }
+
+    /// Divides exactly, so dividing integers results in a rational. Returns
+    /// `None` when dividing an exact number by zero. Floats follow IEEE rules
+    /// instead, so dividing a float by zero results in an infinity or `NaN`.
+    pub fn checked_div(self, other: Number) -> Option<Number> {
+        Some(match promote(self, other) {
+            Promoted::Floats(a, b) => Number::Float(Float(a / b)),
+            Promoted::Ints(_, b) if b.is_zero() => return None,
+            Promoted::Ints(a, b) => Rational::new(a.to_big(), b.to_big()).into(),
+            Promoted::Rationals(_, b) if b.is_zero() => return None,
+            Promoted::Rationals(a, b) => (a / b).into(),
+        })
+    }
+    /// The remainder of a division that rounds towards zero, so it has the
+    /// sign of the dividend. Returns `None` when taking an exact number modulo
+    /// zero.
+    pub fn checked_rem(self, other: Number) -> Option<Number> {
+        Some(match promote(self, other) {
+            Promoted::Floats(a, b) => Number::Float(Float(a % b)),
+            Promoted::Ints(_, b) if b.is_zero() => return None,
+            Promoted::Ints(a, b) => Number::Int(a % b),
+            Promoted::Rationals(_, b) if b.is_zero() => return None,
+            Promoted::Rationals(a, b) => (a % b).into(),
+        })
+    }
+
+    /// Rounds down to an integer. Returns `None` for infinite floats and `NaN`.
+    pub fn floor(self) -> Option<Number> {
+        match self {
+            Number::Int(int) => Some(Number::Int(int)),
+            Number::Rational(rational) => Some(rational.floor().into()),
+            Number::Float(float) => Number::Float(Float(float.0.floor())).into_exact(),
+        }
+    }
+    /// Rounds to the nearest integer, rounding half-way cases away from zero.
+    /// Returns `None` for infinite floats and `NaN`.
+    pub fn round(self) -> Option<Number> {
+        match self {
+            Number::Int(int) => Some(Number::Int(int)),
+            Number::Rational(rational) => Some(rational.round().into()),
+            Number::Float(float) => Number::Float(Float(float.0.round())).into_exact(),
+        }
+    }
+    pub fn into_float(self) -> Number {
+        Number::Float(Float(self.to_f64()))
+    }
+    fn into_exact(self) -> Option<Number> {
+        match self {
+            Number::Float(float) => Some(Rational::from_float(float.0)?.into()),
+            exact => Some(exact),
+        }
+    }
+}
+
+/// Orders numbers by their value. Numbers of different kinds with the same
+/// value, like `1` and `1.0`, are not equal, so integers come before rationals
+/// and rationals before floats in that case.
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        fn rank(number: &Number) -> u8 {
+            match number {
+                Number::Int(_) => 0,
+                Number::Rational(_) => 1,
+                Number::Float(_) => 2,
+            }
+        }
+        self.compare(other)
+            .then_with(|| rank(self).cmp(&rank(other)))
+    }
+}
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<Int> for Number {
+    fn from(int: Int) -> Self {
+        Number::Int(int)
+    }
+}
+impl From<Rational> for Number {
+    fn from(rational: Rational) -> Self {
+        if rational.is_integer() {
+            Number::Int(rational.to_integer().into())
+        } else {
+            Number::Rational(rational)
+        }
+    }
+}
+
+/// Implements an operator that works on all kinds of numbers.
+macro_rules! impl_number_op {
+    ($trait:ident, $method:ident) => {
+        impl $trait for Number {
+            type Output = Number;
+            fn $method(self, other: Number) -> Number {
+                match promote(self, other) {
+                    Promoted::Ints(a, b) => Number::Int(a.$method(b)),
+                    Promoted::Rationals(a, b) => a.$method(b).into(),
+                    Promoted::Floats(a, b) => Number::Float(Float(a.$method(b))),
+                }
+            }
+        }
+    };
+}
+impl_number_op!(Add, add);
+impl_number_op!(Sub, sub);
+impl_number_op!(Mul, mul);
+
+impl std::iter::Sum for Number {
+    fn sum<I: Iterator<Item = Number>>(iter: I) -> Self {
+        iter.fold(Number::Int(Int::Small(0)), Add::add)
+    }
+}
+impl std::iter::Product for Number {
+    fn product<I: Iterator<Item = Number>>(iter: I) -> Self {
+        iter.fold(Number::Int(Int::Small(1)), Mul::mul)
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(int) => write!(f, "{}", int),
+            Number::Rational(rational) => write!(f, "{}", rational),
+            Number::Float(float) => write!(f, "{}", float),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::rc::Rc;
+
+use super::{compile::Chunk, fibers::*, patterns::Arm, run::Call, runtime::*, utils::*, vm::*};
+
+// Every fiber is also an actor: It has a mailbox that others can send messages
+// to, and it receives them using arms like those of a `match`. Messages that
+// don't match any arm stay in the mailbox until an arm that matches them comes
+// along.
+//
+// Actors can watch each other. If two actors are linked and one of them
+// panics, the other one panics as well, wherever it currently waits. An actor
+// that monitors another one gets a message once the other one finishes. A
+// supervised actor that panics starts over with its initial state instead, up
+// to a maximum number of restarts.
+//
+// Actors are referred to by handles like `(:actor, 1)`.
+
+#[derive(Default)]
+pub(super) struct Actor {
+    mailbox: VecDeque<Expr>,
+    /// The actors that panic if this one panics, and the other way round.
+    links: BTreeSet<u64>,
+    /// The actors that get a message once this one finishes.
+    monitors: BTreeSet<u64>,
+    supervisor: Option<Supervisor>,
+}
+/// How to start a supervised actor over.
+struct Supervisor {
+    context: Context,
+    body: Rc<Chunk>,
+    restarts_left: u64,
+}
+
+impl Runtime {
+    /// Finishes a fiber. Supervised fibers that panicked start over instead,
+    /// if they have restarts left.
+    pub(super) fn exit(&mut self, id: u64, result: Result<Context, Panic>) {
+        let actor = handle("actor", id);
+        let fiber = self.scheduler.fibers.get_mut(&id).unwrap();
+        if let (Err(panic), Some(supervisor)) = (&result, &mut fiber.actor.supervisor) {
+            if supervisor.restarts_left > 0 {
+                supervisor.restarts_left -= 1;
+                let stack = Stack::new(supervisor.context.clone(), supervisor.body.clone());
+                fiber.state = FiberState::Ready(stack, None);
+                fiber.depth = 0;
+                let monitors = fiber.actor.monitors.clone();
+                self.scheduler.ready.push_back(id);
+                for monitor in monitors {
+                    let message = tagged("restarted", vec![actor.clone(), panic.value.clone()]);
+                    self.deliver(monitor, message);
+                }
+                return;
+            }
+        }
+
+        let reason = outcome(&result);
+        let linked_panic = match &result {
+            Ok(_) => None,
+            Err(panic) => Some(linked_panic(id, panic.value.clone())),
+        };
+        fiber.state = FiberState::Done(result);
+        let monitors = std::mem::take(&mut fiber.actor.monitors);
+        let links = std::mem::take(&mut fiber.actor.links);
+        for monitor in monitors {
+            let message = tagged("down", vec![actor.clone(), reason.clone()]);
+            self.deliver(monitor, message);
+        }
+        for link in links {
+            let other = self.scheduler.fibers.get_mut(&link).unwrap();
+            other.actor.links.remove(&id);
+            if let Some(value) = &linked_panic {
+                self.interrupt(link, value.clone().into());
+            }
+        }
+    }
+
+    /// Puts a message into the mailbox of an actor. Messages to actors that
+    /// already finished get lost.
+    fn deliver(&mut self, id: u64, message: Expr) {
+        let fiber = self.scheduler.fibers.get_mut(&id).unwrap();
+        if !matches!(fiber.state, FiberState::Done(_)) {
+            fiber.actor.mailbox.push_back(message);
+        }
+    }
+
+    /// Makes a fiber panic where it waits. Fibers that didn't start yet panic
+    /// right away.
+    fn interrupt(&mut self, id: u64, panic: Panic) {
+        let fiber = self.scheduler.fibers.get_mut(&id).unwrap();
+        match std::mem::replace(&mut fiber.state, FiberState::Running) {
+            FiberState::Waiting(stack, _, _) => {
+                fiber.state = FiberState::Ready(stack, Some(Err(panic)));
+                self.scheduler.ready.push_back(id);
+            }
+            FiberState::Ready(stack, Some(_)) => {
+                fiber.state = FiberState::Ready(stack, Some(Err(panic)));
+            }
+            FiberState::Ready(_, None) => {
+                self.scheduler.ready.retain(|ready| *ready != id);
+                self.exit(id, Err(panic));
+            }
+            state => fiber.state = state,
+        }
+    }
+
+    /// Takes the first message out of the mailbox of a fiber that one of the
+    /// arms matches and continues with the code of that arm. Returns nothing
+    /// if there is no such message yet.
+    pub(super) fn try_receive_message(
+        &mut self,
+        id: u64,
+        arms: &[Arm],
+    ) -> Option<Result<Call, Panic>> {
+        let mut index = 0;
+        loop {
+            let message = self.scheduler.fibers[&id].actor.mailbox.get(index)?.clone();
+            match Arm::select(self, arms, &message) {
+                Ok(Some(call)) => {
+                    let fiber = self.scheduler.fibers.get_mut(&id).unwrap();
+                    fiber.actor.mailbox.remove(index);
+                    return Some(Ok(call));
+                }
+                Ok(None) => index += 1,
+                Err(value) => {
+                    let trace = self.take_trace();
+                    return Some(Err(Panic { value, trace }));
+                }
+            }
+        }
+    }
+
+    fn current_fiber(&self) -> u64 {
+        self.scheduler
+            .current
+            .expect("Primitives only run inside of fibers.")
+    }
+    fn needs_actor(&self, expr: Expr, msg: &str) -> Result<u64, Expr> {
+        let id = needs_handle(expr, "actor", msg)?;
+        if !self.scheduler.fibers.contains_key(&id) {
+            return Err(wrong_usage(format!("There's no actor {}.", id)));
+        }
+        Ok(id)
+    }
+}
+
+fn tagged(tag: &str, mut items: Vec<Expr>) -> Expr {
+    items.insert(0, Expr::Symbol(tag.into()));
+    Expr::List(items)
+}
+/// How a fiber finished, like `(:ok, value)` or `(:error, value)`.
+fn outcome(result: &Result<Context, Panic>) -> Expr {
+    match result {
+        Ok(context) => tagged("ok", vec![context.dot.clone()]),
+        Err(panic) => tagged("error", vec![panic.value.clone()]),
+    }
+}
+fn linked_panic(id: u64, value: Expr) -> Expr {
+    tagged("linked-panic", vec![handle("actor", id), value])
+}
+
+impl Context {
+    pub(super) fn primitive_spawn_actor(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "spawn-actor needs an initial state and code.";
+        let (state, code) = self.dot.needs_pair(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        let context = scope.next(runtime, state);
+        let id = runtime.scheduler.spawn(Stack::new(context, body));
+        self.dot = handle("actor", id);
+        Ok(self)
+    }
+
+    /// Spawns an actor that starts over with its initial state if it panics.
+    pub(super) fn primitive_supervise(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "supervise needs an initial state, code and how often the actor may restart.";
+        let (state, code, restarts) = self.dot.needs_list(msg)?.needs_three_items(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        let restarts = restarts.needs_index(msg)? as u64;
+        let context = scope.next(runtime, state);
+        let id = runtime
+            .scheduler
+            .spawn(Stack::new(context.clone(), body.clone()));
+        let fiber = runtime.scheduler.fibers.get_mut(&id).unwrap();
+        fiber.actor.supervisor = Some(Supervisor {
+            context,
+            body,
+            restarts_left: restarts,
+        });
+        self.dot = handle("actor", id);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_self(mut self, runtime: &mut Runtime) -> RunResult {
+        self.dot = handle("actor", runtime.current_fiber());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_send_message(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "send-message needs an actor and a message.";
+        let (actor, message) = self.dot.needs_pair(msg)?;
+        let id = runtime.needs_actor(actor, msg)?;
+        runtime.deliver(id, message);
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+
+    pub(super) fn primitive_receive_message(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let msg = "receive-message needs in turn patterns and code. Patterns can be followed by :| and another pattern, or by :if and guard code.";
+        let arms = Arm::parse_all(self.dot.clone().needs_list(msg)?, msg)?;
+        if arms.is_empty() {
+            return Err(wrong_usage(msg));
+        }
+        let id = runtime.current_fiber();
+        self.wait_for(runtime, Wait::Message(id, arms))
+    }
+
+    /// Links the current actor with another one, so that if one of them
+    /// panics, the other one panics as well.
+    pub(super) fn primitive_link(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "link needs an actor.";
+        let id = runtime.needs_actor(self.dot, msg)?;
+        let current = runtime.current_fiber();
+        if id == current {
+            return Err(wrong_usage("An actor can't link to itself."));
+        }
+        match &runtime.scheduler.fibers[&id].state {
+            FiberState::Done(Err(panic)) => return Err(linked_panic(id, panic.value.clone())),
+            FiberState::Done(Ok(_)) => {}
+            _ => {
+                let fibers = &mut runtime.scheduler.fibers;
+                fibers.get_mut(&id).unwrap().actor.links.insert(current);
+                fibers.get_mut(&current).unwrap().actor.links.insert(id);
+            }
+        }
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+
+    /// Makes the current actor get a `(:down, actor, outcome)` message once
+    /// the other actor finishes.
+    pub(super) fn primitive_monitor(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "monitor needs an actor.";
+        let id = runtime.needs_actor(self.dot, msg)?;
+        let current = runtime.current_fiber();
+        if id == current {
+            return Err(wrong_usage("An actor can't monitor itself."));
+        }
+        match &runtime.scheduler.fibers[&id].state {
+            FiberState::Done(result) => {
+                let message = tagged("down", vec![handle("actor", id), outcome(result)]);
+                runtime.deliver(current, message);
+            }
+            _ => {
+                let fiber = runtime.scheduler.fibers.get_mut(&id).unwrap();
+                fiber.actor.monitors.insert(current);
+            }
+        }
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// Where a host gets the time from. Fibers that `wait` continue once the
+/// clock reaches their deadline.
+pub trait Clock {
+    /// The time that passed since the clock started.
+    fn now(&self) -> Duration;
+    /// Waits until the clock reaches a time. It's only called if no fiber can
+    /// continue before that.
+    fn sleep_until(&mut self, time: Duration);
+}
+
+/// The real time. Sleeping blocks the thread.
+pub struct SystemClock {
+    start: Instant,
+}
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+    fn sleep_until(&mut self, time: Duration) {
+        std::thread::sleep(time.saturating_sub(self.now()));
+    }
+}
+
+/// Simulated time that only passes while all fibers wait for it. Sleeping
+/// jumps to the time right away, so programs that wait finish instantly and
+/// behave the same on every run.
+#[derive(Default)]
+pub struct VirtualClock {
+    now: Duration,
+}
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+    fn sleep_until(&mut self, time: Duration) {
+        self.now = self.now.max(time);
+    }
+}
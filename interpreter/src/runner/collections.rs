@@ -0,0 +1,330 @@
+use num_traits::ToPrimitive;
+
+use super::{compile::Chunk, run::Call, runtime::*, utils::*, vm::*};
+use std::rc::Rc;
+
+// List and map primitives. Lookups of indices or keys that may be missing
+// return `(:some, value)` or `:none`. Indices that have to exist, like the
+// position to insert an item at, result in a `wrong-usage` error instead.
+impl Context {
+    pub(super) fn primitive_get_item(mut self) -> RunResult {
+        let msg = "get-item needs a list and an index.";
+        let (list, index) = self.dot.needs_pair(msg)?;
+        let list = list.needs_list(msg)?;
+        let index = index.needs_int(msg)?;
+        self.dot = match index.to_usize().and_then(|index| list.get(index)) {
+            Some(item) => some(item.clone()),
+            None => none(),
+        };
+        Ok(self)
+    }
+
+    pub(super) fn primitive_get_key(mut self) -> RunResult {
+        let msg = "get-key needs a map and a key.";
+        let (map, key) = self.dot.needs_pair(msg)?;
+        let map = map.needs_map(msg)?;
+        self.dot = match map.get(&key) {
+            Some(value) => some(value.clone()),
+            None => none(),
+        };
+        Ok(self)
+    }
+
+    pub(super) fn primitive_length(mut self) -> RunResult {
+        self.dot = match self.dot {
+            Expr::String(_) => return self.primitive_string_length(),
+            Expr::List(list) => Expr::int(list.len()),
+            Expr::Map(map) => Expr::int(map.len()),
+            _ => return Err(wrong_usage("length needs a string, list or map.")),
+        };
+        Ok(self)
+    }
+
+    pub(super) fn primitive_push(mut self) -> RunResult {
+        let msg = "push needs a list and an item.";
+        let (list, item) = self.dot.needs_pair(msg)?;
+        let mut list = list.needs_list(msg)?;
+        list.push(item);
+        self.dot = Expr::List(list);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_concat(mut self) -> RunResult {
+        let msg = "concat needs a list of strings or a list of lists.";
+        let items = self.dot.clone().needs_list(msg)?;
+        if let Some(Expr::String(_)) = items.first() {
+            return self.primitive_string_concat();
+        }
+        let mut concatenated = vec![];
+        for list in items {
+            concatenated.extend(list.needs_list(msg)?);
+        }
+        self.dot = Expr::List(concatenated);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_slice(mut self) -> RunResult {
+        let msg = "slice needs a string or list, a start index and an end index.";
+        let (list, start, end) = self.dot.clone().needs_list(msg)?.needs_three_items(msg)?;
+        let list = match list {
+            Expr::String(_) => return self.primitive_string_slice(),
+            list => list.needs_list(msg)?,
+        };
+        let start = start.needs_index(msg)?;
+        let end = end.needs_index(msg)?;
+        if start > end || end > list.len() {
+            return Err(wrong_usage(format!(
+                "slice can't take the items from {} to {} of a list with length {}.",
+                start,
+                end,
+                list.len()
+            )));
+        }
+        self.dot = Expr::List(list[start..end].to_vec());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_reverse(mut self) -> RunResult {
+        let mut list = self.dot.needs_list("reverse needs a list.")?;
+        list.reverse();
+        self.dot = Expr::List(list);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_sort(mut self) -> RunResult {
+        let mut list = self.dot.needs_list("sort needs a list.")?;
+        list.sort();
+        self.dot = Expr::List(list);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_insert(mut self) -> RunResult {
+        let msg = "insert needs a list, an index and an item, or a map, a key and a value.";
+        let (collection, key, value) = self.dot.needs_list(msg)?.needs_three_items(msg)?;
+        self.dot = match collection {
+            Expr::List(mut list) => {
+                let index = key.needs_index(msg)?;
+                if index > list.len() {
+                    return Err(wrong_usage(format!(
+                        "insert can't insert at index {} into a list with length {}.",
+                        index,
+                        list.len()
+                    )));
+                }
+                list.insert(index, value);
+                Expr::List(list)
+            }
+            Expr::Map(mut map) => {
+                map.insert(key, value);
+                Expr::Map(map)
+            }
+            _ => return Err(wrong_usage(msg)),
+        };
+        Ok(self)
+    }
+
+    /// Removes an item from a list or a key from a map. Removing a key that
+    /// doesn't exist leaves the map as it is.
+    pub(super) fn primitive_remove(mut self) -> RunResult {
+        let msg = "remove needs a list and an index, or a map and a key.";
+        let (collection, key) = self.dot.needs_pair(msg)?;
+        self.dot = match collection {
+            Expr::List(mut list) => {
+                let index = key.needs_index(msg)?;
+                if index >= list.len() {
+                    return Err(wrong_usage(format!(
+                        "remove can't remove index {} from a list with length {}.",
+                        index,
+                        list.len()
+                    )));
+                }
+                list.remove(index);
+                Expr::List(list)
+            }
+            Expr::Map(mut map) => {
+                map.remove(&key);
+                Expr::Map(map)
+            }
+            _ => return Err(wrong_usage(msg)),
+        };
+        Ok(self)
+    }
+
+    pub(super) fn primitive_keys(mut self) -> RunResult {
+        let map = self.dot.needs_map("keys needs a map.")?;
+        self.dot = Expr::List(map.keys().cloned().collect());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_values(mut self) -> RunResult {
+        let map = self.dot.needs_map("values needs a map.")?;
+        self.dot = Expr::List(map.values().cloned().collect());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_entries(mut self) -> RunResult {
+        let map = self.dot.needs_map("entries needs a map.")?;
+        self.dot = Expr::List(
+            map.into_iter()
+                .map(|(key, value)| Expr::List(vec![key, value]))
+                .collect(),
+        );
+        Ok(self)
+    }
+
+    /// Checks if a list contains an item, a map contains a key, or a string
+    /// contains another string.
+    pub(super) fn primitive_contains(mut self) -> RunResult {
+        let msg = "contains? needs a list and an item, a map and a key, or two strings.";
+        let (collection, item) = self.dot.needs_pair(msg)?;
+        let contains = match collection {
+            Expr::List(list) => list.contains(&item),
+            Expr::Map(map) => map.contains_key(&item),
+            Expr::String(string) => string.contains(&item.needs_string(msg)?),
+            _ => return Err(wrong_usage(msg)),
+        };
+        self.dot = Expr::bool(contains);
+        Ok(self)
+    }
+
+    /// Merges two maps. If both contain a key, the value of the second one wins.
+    pub(super) fn primitive_merge(mut self) -> RunResult {
+        let msg = "merge needs two maps.";
+        let (first, second) = self.dot.needs_pair(msg)?;
+        let first = first.needs_map(msg)?;
+        let second = second.needs_map(msg)?;
+        self.dot = Expr::Map(second.union(first));
+        Ok(self)
+    }
+
+    /// Runs code for each item of a list and collects the results.
+    pub(super) fn primitive_map(self) -> Result<Call, Expr> {
+        let msg = "map needs a list and code.";
+        let (list, code) = self.dot.clone().needs_pair(msg)?;
+        let list = list.needs_list(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        Ok(Call::Task(Box::new(Map {
+            context: Some(self),
+            scope,
+            body,
+            items: list.into_iter(),
+            mapped: vec![],
+        })))
+    }
+
+    /// Keeps the items of a list for which the code returns `:true`.
+    pub(super) fn primitive_filter(self) -> Result<Call, Expr> {
+        let msg = "filter needs a list and code.";
+        let (list, code) = self.dot.clone().needs_pair(msg)?;
+        let list = list.needs_list(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        Ok(Call::Task(Box::new(Filter {
+            context: Some(self),
+            scope,
+            body,
+            items: list.into_iter(),
+            item: None,
+            filtered: vec![],
+        })))
+    }
+
+    /// Combines the items of a list, starting with an initial value. The code
+    /// receives a list of the value so far and the next item.
+    pub(super) fn primitive_fold(self) -> Result<Call, Expr> {
+        let msg = "fold needs a list, an initial value and code.";
+        let (list, initial, code) = self.dot.clone().needs_list(msg)?.needs_three_items(msg)?;
+        let list = list.needs_list(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        Ok(Call::Task(Box::new(Fold {
+            context: Some(self),
+            scope,
+            body,
+            items: list.into_iter(),
+            value: Some(initial),
+        })))
+    }
+}
+
+struct Map {
+    context: Option<Context>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+    items: std::vec::IntoIter<Expr>,
+    mapped: Vec<Expr>,
+}
+impl Task for Map {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr> {
+        Ok(match self.items.next() {
+            Some(item) => Step::run_code(runtime, &self.scope, &self.body, item),
+            None => Step::done(
+                &mut self.context,
+                Expr::List(std::mem::take(&mut self.mapped)),
+            ),
+        })
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        self.mapped.push(result?.dot);
+        self.start(runtime)
+    }
+}
+
+struct Filter {
+    context: Option<Context>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+    items: std::vec::IntoIter<Expr>,
+    /// The item that the code currently runs for.
+    item: Option<Expr>,
+    filtered: Vec<Expr>,
+}
+impl Task for Filter {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr> {
+        Ok(match self.items.next() {
+            Some(item) => {
+                self.item = Some(item.clone());
+                Step::run_code(runtime, &self.scope, &self.body, item)
+            }
+            None => Step::done(
+                &mut self.context,
+                Expr::List(std::mem::take(&mut self.filtered)),
+            ),
+        })
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        let item = self.item.take().expect("filter runs code for an item.");
+        if result?
+            .dot
+            .needs_bool("filter needs code that returns a bool.")?
+        {
+            self.filtered.push(item);
+        }
+        self.start(runtime)
+    }
+}
+
+struct Fold {
+    context: Option<Context>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+    items: std::vec::IntoIter<Expr>,
+    /// The value so far. While the code runs, it has it.
+    value: Option<Expr>,
+}
+impl Task for Fold {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr> {
+        let value = self.value.take().expect("fold has a value.");
+        Ok(match self.items.next() {
+            Some(item) => Step::run_code(
+                runtime,
+                &self.scope,
+                &self.body,
+                Expr::List(vec![value, item]),
+            ),
+            None => Step::done(&mut self.context, value),
+        })
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        self.value = Some(result?.dot);
+        self.start(runtime)
+    }
+}
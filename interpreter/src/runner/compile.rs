@@ -0,0 +1,146 @@
+use super::runtime::*;
+use crate::ast::*;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// Code compiled into a flat list of instructions that the VM runs.
+///
+/// Every piece of code is compiled once, when it's parsed. Code literals in it
+/// are compiled into their own chunks, so creating code values at runtime is
+/// cheap.
+pub struct Chunk {
+    /// The code this chunk was compiled from. It's used for displaying and
+    /// comparing code values.
+    pub asts: Asts,
+    pub(super) ops: Vec<Op>,
+}
+
+pub(super) enum Op {
+    /// Sets the dot to a number, string or symbol.
+    Push(Expr),
+    /// Sets the dot to code that captures the current context.
+    Code(Rc<Chunk>),
+    /// Calls the fun with the name of the slot on the dot.
+    Call {
+        slot: Slot,
+        span: Span,
+        position: CallPosition,
+    },
+    /// Goes one level deeper for building a list, map or string.
+    Nest,
+    /// Saves the current context before running a list item, a map key or
+    /// value, or some code in a string.
+    Enter,
+    /// Pushes the dot onto the value stack and restores the saved context.
+    Leave,
+    /// Builds a list from the given number of values and goes back up a level.
+    List(usize),
+    /// Builds a map from the given number of keys and values and goes back up
+    /// a level.
+    Map(usize),
+    /// Builds a string from the texts and values and goes back up a level.
+    Interpolate(Vec<Piece>),
+    /// Code that couldn't be parsed.
+    Fail,
+}
+
+/// Where a call is in the code. Calls at the end of some code don't need to
+/// remember what to do after they return, so they don't nest deeper.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum CallPosition {
+    Inside,
+    /// The last call of the chunk. It replaces the running function.
+    Tail,
+    /// The last call of a list item, a map key or value, or some code in a
+    /// string.
+    EndOfPart,
+}
+
+pub(super) enum Piece {
+    Text(String),
+    /// The next value on the value stack.
+    Value,
+}
+
+impl Chunk {
+    pub fn compile(asts: Asts) -> Rc<Self> {
+        let mut ops = vec![];
+        compile_sequence(&mut ops, &asts, CallPosition::Tail);
+        Rc::new(Self { asts, ops })
+    }
+}
+
+fn compile_sequence(ops: &mut Vec<Op>, asts: &[Ast], end: CallPosition) {
+    if let Some((last, init)) = asts.split_last() {
+        for ast in init {
+            compile_ast(ops, ast, CallPosition::Inside);
+        }
+        compile_ast(ops, last, end);
+    }
+}
+
+/// Compiles code that runs in a copy of the current context and leaves its
+/// result on the value stack.
+fn compile_part(ops: &mut Vec<Op>, asts: &[Ast]) {
+    ops.push(Op::Enter);
+    compile_sequence(ops, asts, CallPosition::EndOfPart);
+    ops.push(Op::Leave);
+}
+
+fn compile_ast(ops: &mut Vec<Op>, ast: &Ast, position: CallPosition) {
+    match &ast.kind {
+        AstKind::Number(number) => ops.push(Op::Push(Expr::Number(number.clone()))),
+        AstKind::String(string) => ops.push(Op::Push(Expr::String(string.clone()))),
+        AstKind::Symbol(symbol) => ops.push(Op::Push(Expr::Symbol(symbol.clone()))),
+        AstKind::InterpolatedString(parts) => {
+            ops.push(Op::Nest);
+            let mut pieces = vec![];
+            for part in parts {
+                match part {
+                    StringPart::Text(text) => pieces.push(Piece::Text(text.clone())),
+                    StringPart::Code(code) => {
+                        compile_part(ops, code);
+                        pieces.push(Piece::Value);
+                    }
+                }
+            }
+            ops.push(Op::Interpolate(pieces));
+        }
+        AstKind::Map(map) => {
+            ops.push(Op::Nest);
+            for (key, value) in map {
+                compile_part(ops, key);
+                compile_part(ops, value);
+            }
+            ops.push(Op::Map(map.len()));
+        }
+        AstKind::List(list) => {
+            ops.push(Op::Nest);
+            for item in list {
+                compile_part(ops, item);
+            }
+            ops.push(Op::List(list.len()));
+        }
+        AstKind::Code(asts) => ops.push(Op::Code(Chunk::compile(asts.clone()))),
+        // The dot stays the same.
+        AstKind::Name(name) if name == "." => {}
+        AstKind::Name(name) => ops.push(Op::Call {
+            slot: Slot::of(name),
+            span: ast.span,
+            position,
+        }),
+        AstKind::Error => ops.push(Op::Fail),
+    }
+}
+
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.asts == other.asts
+    }
+}
+impl Eq for Chunk {}
+impl Hash for Chunk {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.asts.hash(state)
+    }
+}
@@ -0,0 +1,152 @@
+use std::rc::Rc;
+
+use super::{compile::Chunk, patterns::*, run::Call, runtime::*, utils::*, vm::*};
+
+// Primitives for handling panics. A panic is just a value that travels up as
+// the `Err` of a `RunResult` – usually a list of an error kind and a message
+// like `(:wrong-usage, "...")`, but `panic` can raise anything.
+impl Context {
+    /// Runs code and returns `(:ok, value)` if it succeeds or `(:error, value)`
+    /// if it panics.
+    pub(super) fn primitive_try(self) -> Result<Call, Expr> {
+        let (scope, body) = self.dot.clone().needs_code("try needs code.")?;
+        Ok(Call::Task(Box::new(Try {
+            context: Some(self),
+            scope,
+            body,
+        })))
+    }
+
+    /// Runs code and, if it panics with a value matching the pattern, runs the
+    /// handler instead. The handler gets the panic value as the dot and can use
+    /// the names bound by the pattern. Other panics are not caught.
+    pub(super) fn primitive_catch(self) -> Result<Call, Expr> {
+        let msg = "catch needs code, a pattern and code that handles the panic.";
+        let (code, pattern, handler) = self.dot.clone().needs_list(msg)?.needs_three_items(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        let handler = handler.needs_code(msg)?;
+        Ok(Call::Task(Box::new(Catch {
+            context: Some(self),
+            scope,
+            body,
+            pattern,
+            handler: Some(handler),
+        })))
+    }
+
+    /// Runs code and then the cleanup code, even if the first one panicked.
+    /// A panic of the cleanup code replaces the result of the first one.
+    pub(super) fn primitive_finally(self) -> Result<Call, Expr> {
+        let msg = "finally needs code and cleanup code.";
+        let (code, cleanup) = self.dot.clone().needs_pair(msg)?;
+        let (scope, body) = code.needs_code(msg)?;
+        let cleanup = cleanup.needs_code(msg)?;
+        Ok(Call::Task(Box::new(Finally {
+            context: Some(self),
+            scope,
+            body,
+            cleanup: Some(cleanup),
+            result: None,
+        })))
+    }
+}
+
+struct Try {
+    context: Option<Context>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+}
+impl Task for Try {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr> {
+        Ok(Step::run_code(
+            runtime,
+            &self.scope,
+            &self.body,
+            Expr::unit(),
+        ))
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        let dot = match result {
+            Ok(context) => Expr::List(vec![Expr::Symbol("ok".into()), context.dot]),
+            Err(error) => {
+                runtime.take_trace();
+                Expr::List(vec![Expr::Symbol("error".into()), error])
+            }
+        };
+        Ok(Step::done(&mut self.context, dot))
+    }
+}
+
+struct Catch {
+    context: Option<Context>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+    pattern: Expr,
+    /// The handler, until it runs.
+    handler: Option<(Box<Context>, Rc<Chunk>)>,
+}
+impl Task for Catch {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr> {
+        Ok(Step::run_code(
+            runtime,
+            &self.scope,
+            &self.body,
+            Expr::unit(),
+        ))
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        let error = match result {
+            Ok(context) => return Ok(Step::done(&mut self.context, context.dot)),
+            Err(error) => error,
+        };
+        // Panics of the handler itself are not caught.
+        let (handler_scope, handler_body) = match self.handler.take() {
+            Some(handler) => handler,
+            None => return Err(error),
+        };
+        match match_pattern(&self.pattern, &error) {
+            Ok(bindings) => {
+                runtime.take_trace();
+                let context = bind(runtime, *handler_scope, bindings, error);
+                Ok(Step::Run(context, handler_body))
+            }
+            Err(_) => Err(error),
+        }
+    }
+}
+
+struct Finally {
+    context: Option<Context>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+    /// The cleanup code, until it runs.
+    cleanup: Option<(Box<Context>, Rc<Chunk>)>,
+    /// The result of the code and its stack trace, while the cleanup runs.
+    result: Option<(Result<Expr, Expr>, Vec<Frame>)>,
+}
+impl Task for Finally {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr> {
+        Ok(Step::run_code(
+            runtime,
+            &self.scope,
+            &self.body,
+            Expr::unit(),
+        ))
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        if let Some((cleanup_scope, cleanup_body)) = self.cleanup.take() {
+            let trace = runtime.take_trace();
+            self.result = Some((result.map(|context| context.dot), trace));
+            return Ok(Step::run_code(
+                runtime,
+                &cleanup_scope,
+                &cleanup_body,
+                Expr::unit(),
+            ));
+        }
+        result?;
+        let (result, trace) = self.result.take().expect("finally ran the code.");
+        runtime.restore_trace(trace);
+        Ok(Step::done(&mut self.context, result?))
+    }
+}
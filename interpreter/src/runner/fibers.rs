@@ -0,0 +1,456 @@
+use num_traits::ToPrimitive;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{actors::Actor, compile::Chunk, patterns::Arm, run::Call, runtime::*, utils::*, vm::*};
+
+// Fibers run code concurrently. They are cooperative: A fiber runs until it
+// finishes or waits, for example for another fiber, for a channel or for some
+// time to pass. Then, the next fiber that can continue runs. The code that
+// `Context::run` starts with is the main fiber. Once it finishes, fibers that
+// are still running are stopped.
+//
+// Fibers and channels are referred to by handles like `(:fiber, 1)` and
+// `(:channel, 2)`.
+
+/// Something that a fiber waits for.
+pub(super) enum Wait {
+    Fiber(u64),
+    Receive(u64),
+    Send(u64, Expr),
+    Select(Vec<Case>),
+    /// The time of the runtime's clock to wait for.
+    Sleep(Duration),
+    /// A message for the fiber that matches one of the arms.
+    Message(u64, Vec<Arm>),
+}
+/// A case of a `select`. It receives from the channel, or sends the value to
+/// it, and then runs the code.
+pub(super) struct Case {
+    channel: u64,
+    value: Option<Expr>,
+    scope: Box<Context>,
+    body: Rc<Chunk>,
+}
+
+#[derive(Default)]
+pub(super) struct Scheduler {
+    pub(super) fibers: BTreeMap<u64, Fiber>,
+    /// The fibers that can continue, in the order in which they run.
+    pub(super) ready: VecDeque<u64>,
+    pub(super) current: Option<u64>,
+    next_fiber_id: u64,
+    channels: BTreeMap<u64, Channel>,
+    next_channel_id: u64,
+    /// Chooses the next fiber to run if the runtime has a seed.
+    random: Option<fastrand::Rng>,
+}
+pub(super) struct Fiber {
+    pub(super) state: FiberState,
+    /// How deeply the calls of the fiber are nested.
+    pub(super) depth: u64,
+    pub(super) actor: Actor,
+}
+pub(super) enum FiberState {
+    /// The fiber can continue. If it waited, it continues with the outcome
+    /// of the call it waited for.
+    Ready(Stack, Option<Result<Call, Panic>>),
+    Waiting(Stack, Context, Wait),
+    Running,
+    Done(Result<Context, Panic>),
+}
+#[derive(Clone)]
+pub(super) struct Panic {
+    pub(super) value: Expr,
+    pub(super) trace: Vec<Frame>,
+}
+impl From<Expr> for Panic {
+    fn from(value: Expr) -> Self {
+        Self {
+            value,
+            trace: vec![],
+        }
+    }
+}
+
+struct Channel {
+    capacity: usize,
+    buffer: VecDeque<Expr>,
+    closed: bool,
+}
+
+impl Scheduler {
+    pub(super) fn is_running(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub(super) fn seed(&mut self, seed: u64) {
+        self.random = Some(fastrand::Rng::with_seed(seed));
+    }
+
+    /// Forgets all fibers and channels. The random order goes on, so that
+    /// running more code with the same runtime isn't scheduled the same way.
+    fn reset(&mut self) {
+        let random = self.random.take();
+        *self = Self {
+            random,
+            ..Self::default()
+        };
+    }
+
+    fn next_ready(&mut self) -> Option<u64> {
+        match &mut self.random {
+            Some(random) if !self.ready.is_empty() => {
+                let index = random.usize(..self.ready.len());
+                self.ready.remove(index)
+            }
+            _ => self.ready.pop_front(),
+        }
+    }
+
+    pub(super) fn spawn(&mut self, stack: Stack) -> u64 {
+        let id = self.next_fiber_id;
+        self.next_fiber_id += 1;
+        let fiber = Fiber {
+            state: FiberState::Ready(stack, None),
+            depth: 0,
+            actor: Actor::default(),
+        };
+        self.fibers.insert(id, fiber);
+        self.ready.push_back(id);
+        id
+    }
+
+    fn channel(&mut self, id: u64) -> Result<&mut Channel, Expr> {
+        self.channels
+            .get_mut(&id)
+            .ok_or_else(|| wrong_usage(format!("There's no channel {}.", id)))
+    }
+
+    /// Receives a value from a channel. Returns `(:some, value)`, or `:none`
+    /// if the channel is closed and empty, or nothing if the channel is empty
+    /// but still open.
+    fn try_receive(&mut self, id: u64) -> Option<Expr> {
+        let channel = self.channels.get_mut(&id)?;
+        match channel.buffer.pop_front() {
+            Some(value) => Some(Expr::List(vec![Expr::Symbol("some".into()), value])),
+            None if channel.closed => Some(Expr::Symbol("none".into())),
+            None => None,
+        }
+    }
+
+    /// Sends a value to a channel. Returns nothing if the channel is full.
+    fn try_send(&mut self, id: u64, value: &Expr) -> Option<Result<(), Expr>> {
+        let channel = self.channels.get_mut(&id)?;
+        if channel.closed {
+            Some(Err(error(
+                "closed-channel",
+                "Can't send to a channel that is closed.",
+            )))
+        } else if channel.buffer.len() < channel.capacity {
+            channel.buffer.push_back(value.clone());
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Runtime {
+    /// Runs code as the main fiber, together with all fibers that it spawns,
+    /// until the main fiber finishes.
+    pub(super) fn run_fibers(&mut self, context: Context, chunk: Rc<Chunk>) -> RunResult {
+        let depth = self.depth();
+        let main = self.scheduler.spawn(Stack::new(context, chunk));
+        let result = loop {
+            if let FiberState::Done(result) = &self.scheduler.fibers[&main].state {
+                break result.clone();
+            }
+            match self.scheduler.next_ready() {
+                Some(id) => self.run_fiber(id),
+                None => self.idle(main),
+            }
+        };
+        self.scheduler.reset();
+        self.restore_depth(depth);
+        result.map_err(|panic| {
+            self.restore_trace(panic.trace);
+            panic.value
+        })
+    }
+
+    fn run_fiber(&mut self, id: u64) {
+        let fiber = self.scheduler.fibers.get_mut(&id).unwrap();
+        let (mut stack, resumption) = match std::mem::replace(&mut fiber.state, FiberState::Running)
+        {
+            FiberState::Ready(stack, resumption) => (stack, resumption),
+            _ => unreachable!("Only ready fibers run."),
+        };
+        let depth = fiber.depth;
+        self.restore_depth(depth);
+        self.scheduler.current = Some(id);
+        let resumption = resumption.map(|resumption| {
+            resumption.map_err(|panic| {
+                self.restore_trace(panic.trace);
+                panic.value
+            })
+        });
+        let outcome = run_fiber(self, &mut stack, resumption);
+        self.scheduler.current = None;
+
+        let depth = self.depth();
+        self.scheduler.fibers.get_mut(&id).unwrap().depth = depth;
+        match outcome {
+            Ok(Outcome::Done(context)) => self.exit(id, Ok(context)),
+            Ok(Outcome::Parked(callee, wait)) => {
+                let state = FiberState::Waiting(stack, callee, wait);
+                self.scheduler.fibers.get_mut(&id).unwrap().state = state;
+            }
+            Err(value) => {
+                let trace = self.take_trace();
+                self.exit(id, Err(Panic { value, trace }));
+            }
+        }
+        self.wake_fibers();
+    }
+
+    /// Makes the waiting fibers whose wait is over ready.
+    fn wake_fibers(&mut self) {
+        let ids = self.scheduler.fibers.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            let fiber = self.scheduler.fibers.get_mut(&id).unwrap();
+            let (stack, callee, wait) =
+                match std::mem::replace(&mut fiber.state, FiberState::Running) {
+                    FiberState::Waiting(stack, callee, wait) => (stack, callee, wait),
+                    state => {
+                        fiber.state = state;
+                        continue;
+                    }
+                };
+            // Checking the wait can run guards, which belong to the fiber.
+            self.scheduler.current = Some(id);
+            let resumption = self.try_complete(&callee, &wait);
+            self.scheduler.current = None;
+            let state = match resumption {
+                Some(resumption) => {
+                    self.scheduler.ready.push_back(id);
+                    FiberState::Ready(stack, Some(resumption))
+                }
+                None => FiberState::Waiting(stack, callee, wait),
+            };
+            self.scheduler.fibers.get_mut(&id).unwrap().state = state;
+        }
+    }
+
+    /// Called if no fiber can continue. Sleeps until the next sleeping fiber
+    /// wakes up. If there is none, the fibers wait for each other forever,
+    /// so the main fiber panics.
+    fn idle(&mut self, main: u64) {
+        let wake_up = self
+            .scheduler
+            .fibers
+            .values()
+            .filter_map(|fiber| match &fiber.state {
+                FiberState::Waiting(_, _, Wait::Sleep(deadline)) => Some(*deadline),
+                _ => None,
+            })
+            .min();
+        if let Some(deadline) = wake_up {
+            self.host.sleep_until(deadline);
+            self.wake_fibers();
+            return;
+        }
+        let fiber = self.scheduler.fibers.get_mut(&main).unwrap();
+        let stack = match std::mem::replace(&mut fiber.state, FiberState::Running) {
+            FiberState::Waiting(stack, _, _) => stack,
+            _ => unreachable!("Fibers that are not ready wait."),
+        };
+        let deadlock = error(
+            "deadlock",
+            "All fibers are waiting, so none of them can continue.",
+        );
+        fiber.state = FiberState::Ready(stack, Some(Err(deadlock.into())));
+        self.scheduler.ready.push_back(main);
+    }
+
+    /// Returns the outcome of a call that waits, or nothing if the wait is
+    /// not over yet.
+    fn try_complete(&mut self, callee: &Context, wait: &Wait) -> Option<Result<Call, Panic>> {
+        let returned = |dot: Expr| {
+            let mut callee = callee.clone();
+            callee.dot = dot;
+            Ok(Call::Returned(callee))
+        };
+        match wait {
+            Wait::Fiber(id) => match &self.scheduler.fibers[id].state {
+                FiberState::Done(Ok(context)) => Some(returned(context.dot.clone())),
+                FiberState::Done(Err(panic)) => Some(Err(panic.clone())),
+                _ => None,
+            },
+            Wait::Receive(channel) => self.scheduler.try_receive(*channel).map(returned),
+            Wait::Send(channel, value) => match self.scheduler.try_send(*channel, value)? {
+                Ok(()) => Some(returned(Expr::unit())),
+                Err(error) => Some(Err(error.into())),
+            },
+            Wait::Select(cases) => {
+                for case in cases {
+                    let dot = match &case.value {
+                        None => self.scheduler.try_receive(case.channel),
+                        Some(value) => match self.scheduler.try_send(case.channel, value) {
+                            Some(Ok(())) => Some(Expr::unit()),
+                            Some(Err(error)) => return Some(Err(error.into())),
+                            None => None,
+                        },
+                    };
+                    if let Some(dot) = dot {
+                        let context = (*case.scope).clone().next(self, dot);
+                        return Some(Ok(Call::Continue(context, case.body.clone())));
+                    }
+                }
+                None
+            }
+            Wait::Message(id, arms) => self.try_receive_message(*id, arms),
+            Wait::Sleep(deadline) => {
+                if self.host.now() >= *deadline {
+                    Some(returned(callee.dot.clone()))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+pub(super) fn handle(kind: &str, id: u64) -> Expr {
+    Expr::List(vec![Expr::Symbol(kind.into()), Expr::int(id as usize)])
+}
+pub(super) fn needs_handle(expr: Expr, kind: &str, msg: &str) -> Result<u64, Expr> {
+    let (symbol, id) = expr.needs_pair(msg)?;
+    if symbol.needs_symbol(msg)? != kind {
+        return Err(wrong_usage(msg));
+    }
+    id.needs_int(msg)?.to_u64().needed(msg)
+}
+
+impl Context {
+    /// Completes a call that waits right away if possible. Otherwise, the
+    /// fiber waits.
+    pub(super) fn wait_for(self, runtime: &mut Runtime, wait: Wait) -> Result<Call, Expr> {
+        match runtime.try_complete(&self, &wait) {
+            Some(Ok(call)) => Ok(call),
+            Some(Err(panic)) => {
+                runtime.restore_trace(panic.trace);
+                Err(panic.value)
+            }
+            None => Ok(Call::Park(self, wait)),
+        }
+    }
+
+    pub(super) fn primitive_spawn(mut self, runtime: &mut Runtime) -> RunResult {
+        let (scope, body) = self.dot.needs_code("spawn needs code.")?;
+        let context = scope.next(runtime, Expr::unit());
+        let id = runtime.scheduler.spawn(Stack::new(context, body));
+        self.dot = handle("fiber", id);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_await(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let msg = "await needs a fiber.";
+        let id = needs_handle(self.dot.clone(), "fiber", msg)?;
+        if !runtime.scheduler.fibers.contains_key(&id) {
+            return Err(wrong_usage(format!("There's no fiber {}.", id)));
+        }
+        if runtime.scheduler.current == Some(id) {
+            return Err(wrong_usage("A fiber can't await itself."));
+        }
+        self.wait_for(runtime, Wait::Fiber(id))
+    }
+
+    pub(super) fn primitive_channel(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "channel needs a capacity of at least 1.";
+        let capacity = self.dot.needs_index(msg)?;
+        if capacity == 0 {
+            return Err(wrong_usage(msg));
+        }
+        let scheduler = &mut runtime.scheduler;
+        let id = scheduler.next_channel_id;
+        scheduler.next_channel_id += 1;
+        let channel = Channel {
+            capacity,
+            buffer: VecDeque::new(),
+            closed: false,
+        };
+        scheduler.channels.insert(id, channel);
+        self.dot = handle("channel", id);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_send(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let msg = "send needs a channel and a value.";
+        let (channel, value) = self.dot.clone().needs_pair(msg)?;
+        let id = needs_handle(channel, "channel", msg)?;
+        runtime.scheduler.channel(id)?;
+        self.wait_for(runtime, Wait::Send(id, value))
+    }
+
+    pub(super) fn primitive_receive(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let id = needs_handle(self.dot.clone(), "channel", "receive needs a channel.")?;
+        runtime.scheduler.channel(id)?;
+        self.wait_for(runtime, Wait::Receive(id))
+    }
+
+    pub(super) fn primitive_close(mut self, runtime: &mut Runtime) -> RunResult {
+        let id = needs_handle(self.dot, "channel", "close needs a channel.")?;
+        let channel = runtime.scheduler.channel(id)?;
+        if channel.closed {
+            return Err(error("closed-channel", "The channel is already closed."));
+        }
+        channel.closed = true;
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+
+    pub(super) fn primitive_select(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let msg = "select needs a list of cases. Each case is a channel to receive from or a channel and a value to send, followed by code.";
+        let mut cases = vec![];
+        for case in self.dot.clone().needs_list(msg)? {
+            let (target, code) = case.needs_pair(msg)?;
+            let (channel, value) = match needs_handle(target.clone(), "channel", msg) {
+                Ok(channel) => (channel, None),
+                Err(_) => {
+                    let (channel, value) = target.needs_pair(msg)?;
+                    (needs_handle(channel, "channel", msg)?, Some(value))
+                }
+            };
+            runtime.scheduler.channel(channel)?;
+            let (scope, body) = code.needs_code(msg)?;
+            cases.push(Case {
+                channel,
+                value,
+                scope,
+                body,
+            });
+        }
+        if cases.is_empty() {
+            return Err(wrong_usage(msg));
+        }
+        self.wait_for(runtime, Wait::Select(cases))
+    }
+
+    /// Waits even if no time needs to pass, so `0 wait` lets the other fibers
+    /// run.
+    pub(super) fn primitive_wait(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let seconds = self.dot.clone().needs_int("wait needs an integer.")?;
+        if seconds.is_negative() {
+            return Err(wrong_usage("can't wait a negative number of seconds."));
+        }
+        let seconds = seconds.to_u64().needed("can't wait that long.")?;
+        let deadline = runtime
+            .host
+            .now()
+            .checked_add(Duration::from_secs(seconds))
+            .needed("can't wait that long.")?;
+        Ok(Call::Park(self, Wait::Sleep(deadline)))
+    }
+}
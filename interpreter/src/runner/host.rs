@@ -0,0 +1,221 @@
+use num_traits::ToPrimitive;
+use std::cell::{RefCell, RefMut};
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{clock::*, runtime::*, utils::*};
+use crate::number::{Float, Int, Number};
+
+/// Everything that a Mehl program uses from the world around it, except for
+/// files and processes, which need capabilities. The runtime only talks to the
+/// terminal and the environment through its host, so programs can also run in
+/// tests or inside other programs.
+pub trait Host {
+    /// Writes a line to the standard output.
+    fn print(&mut self, line: &str);
+    /// Writes a line to the standard error.
+    fn print_error(&mut self, line: &str);
+    /// Reads a line from the standard input, without the line break. Returns
+    /// nothing at the end of the input.
+    fn read_line(&mut self) -> Option<String>;
+    fn env_var(&self, name: &str) -> Option<String>;
+    /// The time that passed since the host started.
+    fn now(&self) -> Duration;
+    /// Waits until the time is reached. It's only called if no fiber can
+    /// continue before that.
+    fn sleep_until(&mut self, time: Duration);
+    fn random(&mut self) -> u64;
+}
+
+/// The host of a Mehl program that runs as a process: It uses the standard
+/// input and output, the environment of the process, the system clock and
+/// random numbers that are different on every run.
+pub struct ProcessHost {
+    clock: Box<dyn Clock>,
+    random: fastrand::Rng,
+}
+impl Default for ProcessHost {
+    fn default() -> Self {
+        Self {
+            clock: Box::new(SystemClock::default()),
+            random: fastrand::Rng::new(),
+        }
+    }
+}
+impl ProcessHost {
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+    /// Makes the random numbers only depend on the seed.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            random: fastrand::Rng::with_seed(seed),
+            ..self
+        }
+    }
+}
+impl Host for ProcessHost {
+    fn print(&mut self, line: &str) {
+        println!("{}", line);
+    }
+    fn print_error(&mut self, line: &str) {
+        eprintln!("{}", line);
+    }
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                let len = line.trim_end_matches(&['\n', '\r'][..]).len();
+                line.truncate(len);
+                Some(line)
+            }
+        }
+    }
+    fn env_var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+    fn now(&self) -> Duration {
+        self.clock.now()
+    }
+    fn sleep_until(&mut self, time: Duration) {
+        self.clock.sleep_until(time)
+    }
+    fn random(&mut self) -> u64 {
+        self.random.u64(..)
+    }
+}
+
+/// A host that keeps everything in memory, for tests and for embedding Mehl
+/// into other programs. Its time is virtual and its random numbers are always
+/// the same. Clones share their memory, so one clone can be given to the
+/// runtime while the other one provides input and checks the output.
+#[derive(Clone, Default)]
+pub struct MemoryHost(Rc<RefCell<Memory>>);
+pub struct Memory {
+    /// The lines that the program reads, in order.
+    pub input: VecDeque<String>,
+    /// The lines that the program printed to the standard output.
+    pub output: Vec<String>,
+    /// The lines that the program printed to the standard error.
+    pub errors: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub clock: VirtualClock,
+    pub random: fastrand::Rng,
+}
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            input: VecDeque::new(),
+            output: vec![],
+            errors: vec![],
+            env: HashMap::new(),
+            clock: VirtualClock::default(),
+            random: fastrand::Rng::with_seed(0),
+        }
+    }
+}
+impl MemoryHost {
+    pub fn memory(&self) -> RefMut<'_, Memory> {
+        self.0.borrow_mut()
+    }
+}
+impl Host for MemoryHost {
+    fn print(&mut self, line: &str) {
+        self.memory().output.push(line.to_string());
+    }
+    fn print_error(&mut self, line: &str) {
+        self.memory().errors.push(line.to_string());
+    }
+    fn read_line(&mut self) -> Option<String> {
+        self.memory().input.pop_front()
+    }
+    fn env_var(&self, name: &str) -> Option<String> {
+        self.0.borrow().env.get(name).cloned()
+    }
+    fn now(&self) -> Duration {
+        self.0.borrow().clock.now()
+    }
+    fn sleep_until(&mut self, time: Duration) {
+        self.memory().clock.sleep_until(time)
+    }
+    fn random(&mut self) -> u64 {
+        self.memory().random.u64(..)
+    }
+}
+
+impl Runtime {
+    /// Returns a random number below the bound. Unlike taking a random number
+    /// modulo the bound, it doesn't favor small numbers.
+    fn random_below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let random = self.host.random();
+            if random < zone {
+                return random % bound;
+            }
+        }
+    }
+}
+
+// Primitives that talk to the host.
+impl Context {
+    pub(super) fn primitive_print_error(self, runtime: &mut Runtime) -> Self {
+        runtime.print_error(&self.dot);
+        self
+    }
+
+    /// Reads a line. Returns `(:some, line)` or `:none` at the end of the
+    /// input.
+    pub(super) fn primitive_read_line(mut self, runtime: &mut Runtime) -> Self {
+        self.dot = match runtime.host.read_line() {
+            Some(line) => some(Expr::String(line)),
+            None => none(),
+        };
+        self
+    }
+
+    pub(super) fn primitive_env_var(mut self, runtime: &mut Runtime) -> RunResult {
+        let name = self.dot.needs_string("env-var needs a string.")?;
+        self.dot = match runtime.host.env_var(&name) {
+            Some(value) => some(Expr::String(value)),
+            None => none(),
+        };
+        Ok(self)
+    }
+
+    /// Returns the seconds since the program started as a float.
+    pub(super) fn primitive_now(mut self, runtime: &mut Runtime) -> Self {
+        self.dot = Expr::Number(Number::Float(Float(runtime.host.now().as_secs_f64())));
+        self
+    }
+
+    /// Returns a random integer between a minimum and a maximum, both
+    /// included.
+    pub(super) fn primitive_random_int(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "random-int needs a minimum and a maximum integer that fit into 64 bits.";
+        let (min, max) = self.dot.needs_pair(msg)?;
+        let min = min.needs_int(msg)?.to_i64().needed(msg)?;
+        let max = max.needs_int(msg)?.to_i64().needed(msg)?;
+        if min > max {
+            return Err(wrong_usage(format!(
+                "random-int needs a minimum that is not bigger than the maximum, but got {} and {}.",
+                min, max
+            )));
+        }
+        let width = (max as i128 - min as i128 + 1) as u128;
+        let offset = match width.to_u64() {
+            Some(width) => runtime.random_below(width),
+            // The range contains all 64-bit integers.
+            None => runtime.host.random(),
+        };
+        let random = (min as i128 + offset as i128) as i64;
+        self.dot = Expr::Number(Number::Int(Int::from(random)));
+        Ok(self)
+    }
+}
@@ -1,7 +1,21 @@
+mod actors;
+mod clock;
+mod collections;
+mod compile;
+mod errors;
+mod fibers;
+mod host;
+mod patterns;
 mod run;
 mod runtime;
+mod strings;
+mod system;
 mod utils;
+mod vm;
 
-pub use run::*;
+pub use clock::*;
+pub use compile::Chunk;
+pub use host::*;
 pub use runtime::*;
+pub use system::Capabilities;
 pub use utils::*;
@@ -0,0 +1,492 @@
+use im::{HashMap, OrdMap};
+use std::fmt;
+use std::rc::Rc;
+
+use super::{compile::Chunk, run::Call, runtime::*, utils::*};
+use crate::ast::format_code;
+use crate::number::Number;
+
+// Patterns are ordinary values that describe the shape of other values. Most
+// values only match themselves, but some symbols have a special meaning:
+//
+// * `:_` matches anything.
+// * `:?name` matches anything and binds it to `name`.
+// * `:?name:number` and `:_:number` only match numbers. Symbols can't contain
+//   colons, so these can't be written directly. Instead, `typed` creates them
+//   from one of the symbols above and a type, like `(:?n, :number) typed`.
+// * `:..?rest` as an item of a list pattern matches all the items that the
+//   other patterns don't match and binds them to `rest`. `:..` ignores them.
+//
+// List patterns match lists with exactly as many items, unless they contain a
+// rest pattern. Map patterns are open and match maps that have at least the
+// keys they mention. If they contain the key `:..`, its value is matched
+// against a map of all the other entries. As a special case, `:.., :none`
+// closes the pattern, so it only matches maps without other keys.
+
+pub type Bindings = HashMap<String, Expr>;
+
+/// The types that values can be checked for using `(:is-type, (value, type))`
+/// and that typed patterns can require.
+const TYPES: [&str; 8] = [
+    "number", "int", "string", "symbol", "bool", "list", "map", "code",
+];
+
+fn has_type(value: &Expr, type_: &str) -> bool {
+    match type_ {
+        "number" => matches!(value, Expr::Number(_)),
+        "int" => matches!(value, Expr::Number(Number::Int(_))),
+        "string" => matches!(value, Expr::String(_)),
+        "symbol" => matches!(value, Expr::Symbol(_)),
+        "bool" => matches!(value, Expr::Symbol(symbol) if symbol == "true" || symbol == "false"),
+        "list" => matches!(value, Expr::List(_)),
+        "map" => matches!(value, Expr::Map(_)),
+        "code" => matches!(value, Expr::Code { .. }),
+        _ => false,
+    }
+}
+
+/// A symbol pattern that matches any value, possibly only of one type, and
+/// possibly binds it to a name.
+struct Wildcard<'a> {
+    name: Option<&'a str>,
+    type_: Option<&'a str>,
+}
+impl<'a> Wildcard<'a> {
+    fn parse(symbol: &'a str) -> Option<Self> {
+        let (head, type_) = match symbol.split_once(':') {
+            Some((head, type_)) => {
+                if !TYPES.contains(&type_) {
+                    return None;
+                }
+                (head, Some(type_))
+            }
+            None => (symbol, None),
+        };
+        let name = match head {
+            "_" => None,
+            _ => Some(head.strip_prefix('?').filter(|name| !name.is_empty())?),
+        };
+        Some(Self { name, type_ })
+    }
+}
+
+/// Returns the name that the rest pattern binds to, `Some(None)` if it ignores
+/// the rest, or `None` if the pattern is no rest pattern.
+fn rest_pattern(pattern: &Expr) -> Option<Option<&str>> {
+    let rest = match pattern {
+        Expr::Symbol(symbol) => symbol.strip_prefix("..")?,
+        _ => return None,
+    };
+    match rest {
+        "" | "_" => Some(None),
+        _ => Some(Some(
+            rest.strip_prefix('?').filter(|name| !name.is_empty())?,
+        )),
+    }
+}
+
+/// Why a value doesn't match a pattern, and where in the value that happened.
+pub struct Mismatch {
+    /// The way from the value to the part that doesn't match, innermost first.
+    path: Vec<Segment>,
+    reason: Box<Reason>,
+}
+enum Segment {
+    Item(usize),
+    Key(Expr),
+    Rest,
+}
+enum Reason {
+    NotEqual {
+        pattern: Expr,
+        value: Expr,
+    },
+    WrongType {
+        type_: String,
+        value: Expr,
+    },
+    WrongLength {
+        expected: usize,
+        at_least: bool,
+        actual: usize,
+    },
+    MissingKey(Expr),
+    UnexpectedKeys(Vec<Expr>),
+    Conflict {
+        name: String,
+        value: Expr,
+        existing: Expr,
+    },
+}
+impl Mismatch {
+    fn new(reason: Reason) -> Self {
+        Self {
+            path: vec![],
+            reason: Box::new(reason),
+        }
+    }
+    fn at(mut self, segment: Segment) -> Self {
+        self.path.push(segment);
+        self
+    }
+}
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subject = if self.path.is_empty() {
+            "the value".to_string()
+        } else {
+            itertools::join(
+                self.path.iter().map(|segment| match segment {
+                    Segment::Item(index) => format!("list item {}", index),
+                    Segment::Key(key) => format!("key {}", key),
+                    Segment::Rest => "the rest".to_string(),
+                }),
+                " of ",
+            )
+        };
+        match &*self.reason {
+            Reason::NotEqual { pattern, value } => {
+                write!(f, "{} is {}, but {} was expected.", subject, value, pattern)
+            }
+            Reason::WrongType { type_, value } => {
+                write!(f, "{} is {}, which is no {}.", subject, value, type_)
+            }
+            Reason::WrongLength {
+                expected,
+                at_least,
+                actual,
+            } => write!(
+                f,
+                "{} has a length of {}, but {}{} was expected.",
+                subject,
+                actual,
+                if *at_least { "at least " } else { "" },
+                expected
+            ),
+            Reason::MissingKey(key) => write!(f, "{} has no key {}.", subject, key),
+            Reason::UnexpectedKeys(keys) => write!(
+                f,
+                "{} has the unexpected keys {}.",
+                subject,
+                itertools::join(keys, ", ")
+            ),
+            Reason::Conflict {
+                name,
+                value,
+                existing,
+            } => write!(
+                f,
+                "{} binds {} to {}, but it's already bound to {}.",
+                subject, name, value, existing
+            ),
+        }
+    }
+}
+
+/// Adds bindings to existing ones. If a name is bound twice, both values need
+/// to be equal.
+fn unify(unified: &mut Bindings, bindings: Bindings) -> Result<(), Mismatch> {
+    for (name, value) in bindings {
+        match unified.get(&name) {
+            Some(existing) if existing != &value => {
+                return Err(Mismatch::new(Reason::Conflict {
+                    name,
+                    value,
+                    existing: existing.clone(),
+                }))
+            }
+            Some(_) => {}
+            None => {
+                unified.insert(name, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Matches a value against a pattern. Returns the bound names if it matches.
+pub fn match_pattern(pattern: &Expr, value: &Expr) -> Result<Bindings, Mismatch> {
+    let not_equal = || {
+        Mismatch::new(Reason::NotEqual {
+            pattern: pattern.clone(),
+            value: value.clone(),
+        })
+    };
+    let wrong_type = |type_: &str| {
+        Mismatch::new(Reason::WrongType {
+            type_: type_.to_string(),
+            value: value.clone(),
+        })
+    };
+    match pattern {
+        Expr::Symbol(symbol) => match Wildcard::parse(symbol) {
+            Some(wildcard) => {
+                if let Some(type_) = wildcard.type_ {
+                    if !has_type(value, type_) {
+                        return Err(wrong_type(type_));
+                    }
+                }
+                let mut bindings = HashMap::new();
+                if let Some(name) = wildcard.name {
+                    bindings.insert(name.to_string(), value.clone());
+                }
+                Ok(bindings)
+            }
+            None if pattern == value => Ok(HashMap::new()),
+            None => Err(not_equal()),
+        },
+        Expr::List(patterns) => match value {
+            Expr::List(items) => match_list(patterns, items),
+            _ => Err(wrong_type("list")),
+        },
+        Expr::Map(patterns) => match value {
+            Expr::Map(entries) => match_map(patterns, entries),
+            _ => Err(wrong_type("map")),
+        },
+        _ if pattern == value => Ok(HashMap::new()),
+        _ => Err(not_equal()),
+    }
+}
+
+fn match_list(patterns: &[Expr], items: &[Expr]) -> Result<Bindings, Mismatch> {
+    let mut unified = HashMap::new();
+    let rest_index = patterns.iter().position(|it| rest_pattern(it).is_some());
+    let (before, after) = match rest_index {
+        Some(index) => (&patterns[..index], &patterns[index + 1..]),
+        None => (patterns, &[][..]),
+    };
+    let fixed = before.len() + after.len();
+    if items.len() < fixed || (rest_index.is_none() && items.len() != fixed) {
+        return Err(Mismatch::new(Reason::WrongLength {
+            expected: fixed,
+            at_least: rest_index.is_some(),
+            actual: items.len(),
+        }));
+    }
+    let rest_end = items.len() - after.len();
+    let fixed_items = (0..before.len()).chain(rest_end..items.len());
+    for (pattern, index) in before.iter().chain(after).zip(fixed_items) {
+        let bindings =
+            match_pattern(pattern, &items[index]).map_err(|it| it.at(Segment::Item(index)))?;
+        unify(&mut unified, bindings)?;
+    }
+    if let Some(Some(name)) = rest_index.and_then(|index| rest_pattern(&patterns[index])) {
+        let rest = Expr::List(items[before.len()..rest_end].to_vec());
+        unify(&mut unified, HashMap::unit(name.to_string(), rest))?;
+    }
+    Ok(unified)
+}
+
+fn match_map(
+    patterns: &OrdMap<Expr, Expr>,
+    entries: &OrdMap<Expr, Expr>,
+) -> Result<Bindings, Mismatch> {
+    let mut unified = HashMap::new();
+    let mut rest = entries.clone();
+    for (key, pattern) in patterns {
+        if key == &Expr::Symbol("..".into()) {
+            continue;
+        }
+        let value = rest
+            .remove(key)
+            .ok_or_else(|| Mismatch::new(Reason::MissingKey(key.clone())))?;
+        let bindings =
+            match_pattern(pattern, &value).map_err(|it| it.at(Segment::Key(key.clone())))?;
+        unify(&mut unified, bindings)?;
+    }
+    match patterns.get(&Expr::Symbol("..".into())) {
+        Some(Expr::Symbol(symbol)) if symbol == "none" && !rest.is_empty() => {
+            return Err(Mismatch::new(Reason::UnexpectedKeys(
+                rest.keys().cloned().collect(),
+            )))
+        }
+        Some(Expr::Symbol(symbol)) if symbol == "none" => {}
+        Some(pattern) => {
+            let bindings =
+                match_pattern(pattern, &Expr::Map(rest)).map_err(|it| it.at(Segment::Rest))?;
+            unify(&mut unified, bindings)?;
+        }
+        None => {}
+    }
+    Ok(unified)
+}
+
+/// Turns the names that `let` defines into a pattern. In `let`, symbols like
+/// `:a` bind values directly and `:..rest` binds the rest of a list. So
+/// `(:a, {:b, :c}, :..d)` becomes `(:?a, {:b, :?c}, :..?d)`.
+pub fn let_pattern(names: &Expr) -> Result<Expr, Expr> {
+    Ok(match names {
+        Expr::Symbol(name) => {
+            let (prefix, name) = match name.strip_prefix("..") {
+                Some(name) => ("..", name),
+                None => ("", name.as_str()),
+            };
+            Expr::Symbol(match name {
+                "" if prefix.is_empty() => {
+                    return Err(wrong_usage("let can't define a fun with an empty name."))
+                }
+                "" | "_" => format!("{}_", prefix),
+                _ => format!("{}?{}", prefix, name),
+            })
+        }
+        Expr::List(names) => Expr::List(names.iter().map(let_pattern).collect::<Result<_, _>>()?),
+        Expr::Map(names) => Expr::Map(
+            names
+                .iter()
+                .map(|(key, names)| Ok((key.clone(), let_pattern(names)?)))
+                .collect::<Result<_, Expr>>()?,
+        ),
+        literal => literal.clone(),
+    })
+}
+
+/// Creates a context for code in which the bindings are defined.
+pub fn bind(runtime: &mut Runtime, scope: Context, bindings: Bindings, dot: Expr) -> Context {
+    let mut context = scope.next(runtime, dot);
+    for (name, value) in bindings {
+        context
+            .funs
+            .insert(Fun::new(name, None, FunBody::Value(Rc::new(value)), 0));
+    }
+    context
+}
+
+/// An arm of a match: Some alternative patterns, an optional guard and the
+/// code to run.
+pub(super) struct Arm {
+    patterns: Vec<Expr>,
+    guard: Option<(Box<Context>, Rc<Chunk>)>,
+    body: (Box<Context>, Rc<Chunk>),
+}
+impl Arm {
+    /// Parses arms, which look like this:
+    /// `pattern, :|, another-pattern, :if, [guard], [code]`
+    pub(super) fn parse_all(items: Vec<Expr>, msg: &str) -> Result<Vec<Arm>, Expr> {
+        let mut items = items.into_iter().peekable();
+        let mut arms = vec![];
+        while let Some(pattern) = items.next() {
+            let mut patterns = vec![pattern];
+            while items.next_if_eq(&Expr::Symbol("|".into())).is_some() {
+                patterns.push(items.next().needed(msg)?);
+            }
+            let guard = match items.next_if_eq(&Expr::Symbol("if".into())) {
+                Some(_) => Some(items.next().needed(msg)?.needs_code(msg)?),
+                None => None,
+            };
+            let body = items.next().needed(msg)?.needs_code(msg)?;
+            arms.push(Arm {
+                patterns,
+                guard,
+                body,
+            });
+        }
+        Ok(arms)
+    }
+
+    /// Continues with the code of the first arm with a pattern that matches
+    /// the value and whose guard returns `:true`. Guards get the value as the
+    /// dot.
+    pub(super) fn select(
+        runtime: &mut Runtime,
+        arms: &[Arm],
+        value: &Expr,
+    ) -> Result<Option<Call>, Expr> {
+        for arm in arms {
+            for pattern in &arm.patterns {
+                let bindings = match match_pattern(pattern, value) {
+                    Ok(bindings) => bindings,
+                    Err(_) => continue,
+                };
+                if let Some((scope, guard)) = &arm.guard {
+                    let accepts = bind(runtime, (**scope).clone(), bindings.clone(), value.clone())
+                        .run(runtime, guard.clone())?
+                        .dot
+                        .needs_bool("match guards need to return :true or :false.")?;
+                    if !accepts {
+                        continue;
+                    }
+                }
+                let (scope, body) = &arm.body;
+                return Ok(Some(Call::Continue(
+                    bind(runtime, (**scope).clone(), bindings, Expr::unit()),
+                    body.clone(),
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
+impl fmt::Display for Arm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", itertools::join(&self.patterns, " | "))?;
+        if let Some((_, guard)) = &self.guard {
+            write!(f, " if [{}]", format_code(&guard.asts))?;
+        }
+        Ok(())
+    }
+}
+
+impl Context {
+    /// Runs the code of the first arm that matches the value.
+    pub(super) fn primitive_match(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let mut items = self.dot.needs_list("match needs a list.")?.into_iter();
+        let value = items.next().needed(
+            "match needs a list with at least 3 items – the value, a pattern, and some code.",
+        )?;
+        let msg = "match needs a value, and then in turn patterns and code. Patterns can be followed by :| and another pattern, or by :if and guard code.";
+        let arms = Arm::parse_all(items.collect(), msg)?;
+        if arms.is_empty() {
+            return Err(wrong_usage(
+                "match needs a list with at least 3 items – the value, a pattern, and some code.",
+            ));
+        }
+
+        if let Some(call) = Arm::select(runtime, &arms, &value)? {
+            return Ok(call);
+        }
+        Err(error(
+            "no-match",
+            format!(
+                "{} doesn't match any of the patterns. Tried: {}",
+                value,
+                itertools::join(&arms, "; ")
+            ),
+        ))
+    }
+
+    /// Checks if a value has a type.
+    pub(super) fn primitive_is_type(mut self) -> RunResult {
+        let msg = "is-type needs a value and a type.";
+        let (value, type_) = self.dot.needs_pair(msg)?;
+        let type_ = needs_type(type_, "is-type", msg)?;
+        self.dot = Expr::bool(has_type(&value, &type_));
+        Ok(self)
+    }
+
+    /// Turns a pattern that matches anything, like `:?n` or `:_`, into one
+    /// that only matches values of a type.
+    pub(super) fn primitive_typed(mut self) -> RunResult {
+        let msg = "typed needs a pattern like :?name or :_ and a type.";
+        let (pattern, type_) = self.dot.needs_pair(msg)?;
+        let pattern = pattern.needs_symbol(msg)?;
+        let type_ = needs_type(type_, "typed", msg)?;
+        if pattern.contains(':') || Wildcard::parse(&pattern).is_none() {
+            return Err(wrong_usage(msg));
+        }
+        self.dot = Expr::Symbol(format!("{}:{}", pattern, type_));
+        Ok(self)
+    }
+}
+
+fn needs_type(type_: Expr, primitive: &str, msg: &str) -> Result<String, Expr> {
+    let type_ = type_.needs_symbol(msg)?;
+    if !TYPES.contains(&type_.as_str()) {
+        return Err(wrong_usage(format!(
+            "{} doesn't know the type {}. Known types are {}.",
+            primitive,
+            type_,
+            TYPES.join(", ")
+        )));
+    }
+    Ok(type_)
+}
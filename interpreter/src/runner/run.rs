@@ -1,101 +1,105 @@
-use colored::*;
-use im::HashMap;
-use itertools::Itertools;
+use std::cmp::Ordering;
 use std::rc::Rc;
 
-use super::{runtime::*, utils::*};
+use super::{compile::Chunk, fibers::Wait, patterns::*, runtime::*, utils::*, vm::*};
 use crate::ast::*;
+use crate::number::Number;
+use num_traits::ToPrimitive;
+
+/// The result of calling a function.
+pub(super) enum Call {
+    /// The function is done and this is its context.
+    Returned(Context),
+    /// The function still needs to run this code in this context. Calls in
+    /// tail position run it without nesting deeper, so that recursive functions
+    /// don't run out of stack.
+    Continue(Context, Rc<Chunk>),
+    /// The function runs code through a task, like `map` does.
+    Task(Box<dyn Task>),
+    /// The function waits, for example for a value from a channel. Once the
+    /// wait is over, the fiber continues with the outcome of the call.
+    Park(Context, Wait),
+}
 
 impl Context {
-    pub fn run(self, runtime: &mut Runtime, code: Asts) -> RunResult {
-        let mut context = self.clone();
-        for ast in code {
-            context = context.run_single(runtime, ast)?;
-        }
-        Ok(context)
+    pub(super) fn call(&self, runtime: &mut Runtime, slot: Slot) -> Result<Call, Expr> {
+        let fun = self
+            .funs
+            .get(slot)
+            .ok_or_else(|| unknown_function(&*slot.name()))?;
+        Ok(match self.select_overload(runtime, fun)? {
+            FunBody::Primitive => self.clone().primitive(runtime)?,
+            FunBody::Code { scope, body } => {
+                let mut context = (*scope).clone().next(runtime, self.dot.clone());
+                // Functions can call themselves, although they are defined
+                // after the scope of their code was captured.
+                context.funs.insert_at(
+                    slot,
+                    Fun {
+                        export_level: 0,
+                        ..fun.clone()
+                    },
+                );
+                Call::Continue(context, body)
+            }
+            FunBody::Value(expr) => Call::Returned(self.clone().next(runtime, (*expr).clone())),
+        })
     }
 
-    fn run_single(self, runtime: &mut Runtime, ast: Ast) -> RunResult {
-        runtime.log(&format!(
-            "Running {} on {}. Funs: {}",
-            format_code(&vec![ast.clone()]).yellow(),
-            self.dot.to_string().green(),
-            self.funs.to_fancy_string(),
-        ));
-        Ok(match ast {
-            Ast::Number(number) => self.next(runtime, Expr::Number(number)),
-            Ast::String(string) => self.next(runtime, Expr::String(string)),
-            Ast::Symbol(symbol) => self.next(runtime, Expr::Symbol(symbol)),
-            Ast::Map(map) => {
-                let mut expr_map = HashMap::new();
-                let context = self.clone();
-                runtime.depth_increase();
-                for (key, value) in map {
-                    expr_map.insert(
-                        context.clone().run(runtime, key)?.dot,
-                        context.clone().run(runtime, value)?.dot,
-                    );
-                }
-                runtime.depth_decrease();
-                self.next(runtime, Expr::Map(expr_map))
-            }
-            Ast::List(list) => {
-                let mut expr_list = vec![];
-                let context = self.clone();
-                runtime.depth_increase();
-                for item in list {
-                    expr_list.push(context.clone().run(runtime, item)?.dot);
-                }
-                runtime.depth_decrease();
-                self.next(runtime, Expr::List(expr_list))
+    /// Continues after a call returned. The functions that the callee
+    /// exported are exported the given number of times into this context.
+    pub(super) fn returned(&mut self, runtime: &mut Runtime, callee: Context, exports: u32) {
+        self.advance(runtime, callee.dot);
+        for fun in callee.funs.exported() {
+            if u32::from(fun.export_level) >= exports {
+                let mut fun = fun.clone();
+                fun.export_level -= exports as u16;
+                self.funs.define(fun);
             }
-            Ast::Code(asts) => self.clone().next(
-                runtime,
-                Expr::Code {
-                    scope: Box::new(self),
-                    asts,
-                },
-            ),
-            Ast::Name(name) => {
-                if name == "." {
-                    return Ok(self.clone());
-                }
-                let fun = self.funs.get(&name).ok_or(unknown_function(name.clone()))?;
-                runtime.depth_increase();
-                let context = match fun.body.clone() {
-                    FunBody::Primitive => self.clone().primitive(runtime)?,
-                    FunBody::Code { scope, body } => (*scope)
-                        .clone()
-                        .next(runtime, self.dot.clone())
-                        .run(runtime, body.to_vec())?,
-                    FunBody::Value(expr) => self.clone().next(runtime, (*expr).clone()),
-                };
-                runtime.depth_decrease();
-                let mut next_context = self.clone().next(runtime, context.dot);
-                for (name, fun) in context.funs.clone() {
-                    if fun.export_level >= 1 {
-                        let mut fun = fun.clone();
-                        fun.export_level -= 1;
-                        next_context.funs.insert(name, fun);
-                    } else {
-                        runtime.log(&format!("Not exporting {}.", name));
-                    }
-                }
-                runtime.log(&format!(
-                    "Exited fun {}. Dot: {}, Funs: {}",
-                    name.magenta(),
-                    self.dot.to_string().green(),
-                    self.funs.to_fancy_string(),
-                ));
-                next_context
+        }
+    }
+
+    pub(super) fn frame(&self, name: &str, span: Span) -> Frame {
+        Frame {
+            name: name.to_string(),
+            source: self.source.clone(),
+            span,
+            dot: summarize(&self.dot),
+            repeated: 0,
+        }
+    }
+
+    /// Returns the body of the first overload of the fun whose guard accepts
+    /// the dot.
+    fn select_overload(&self, runtime: &mut Runtime, fun: &Fun) -> Result<FunBody, Expr> {
+        for overload in &fun.overloads {
+            let accepts = match &overload.guard {
+                None => true,
+                Some(guard) => (*guard.scope)
+                    .clone()
+                    .next(runtime, self.dot.clone())
+                    .run(runtime, guard.body.clone())?
+                    .dot
+                    .needs_bool(format!(
+                        "The guards of {} need to return :true or :false.",
+                        fun.name
+                    ))?,
+            };
+            if accepts {
+                return Ok(overload.body.clone());
             }
-        })
+        }
+        Err(error(
+            "no-matching-overload",
+            format!("No overload of {} accepts {}.", fun.name, self.dot),
+        ))
     }
 }
 
 // Primitives.
 impl Context {
-    fn primitive(self, runtime: &mut Runtime) -> RunResult {
+    fn primitive(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        use Ordering::*;
         let (name, arg) = self
             .dot
             .clone()
@@ -103,36 +107,109 @@ impl Context {
             .needs_two_items("✨ needs a list with two items.")?;
         let name = name.needs_symbol("✨ needs a symbol as the first tuple item")?;
         let context = self.clone().next(runtime, arg.clone());
-        match name.as_ref() {
+        let context = match name.as_ref() {
+            "=" => context.primitive_is_equal(),
+            "<" => context.primitive_is_ordered("<", &[Less]),
+            ">" => context.primitive_is_ordered(">", &[Greater]),
+            "<=" => context.primitive_is_ordered("<=", &[Less, Equal]),
+            ">=" => context.primitive_is_ordered(">=", &[Greater, Equal]),
+            "*" => context.primitive_numbers_multiply(),
             "+" => context.primitive_numbers_add(),
             "-" => context.primitive_numbers_subtract(),
-            "*" => context.primitive_numbers_multiply(),
             "/" => context.primitive_numbers_divide(),
+            "await" => return context.primitive_await(runtime),
+            "catch" => return context.primitive_catch(),
+            "channel" => context.primitive_channel(runtime),
+            "close" => context.primitive_close(runtime),
+            "compare" => context.primitive_compare(),
+            "concat" => context.primitive_concat(),
+            "contains?" => context.primitive_contains(),
+            "create-dir" => context.primitive_create_dir(runtime),
+            "ends-with?" => context.primitive_string_ends_with(),
+            "entries" => context.primitive_entries(),
+            "env-var" => context.primitive_env_var(runtime),
+            "eval" => return context.primitive_eval(runtime),
+            "exists?" => context.primitive_exists(runtime),
             "export-all" => Ok(context.primitive_export_all()),
+            "filter" => return context.primitive_filter(),
+            "finally" => return context.primitive_finally(),
+            "find" => context.primitive_string_find(),
+            "floor" => context.primitive_numbers_floor(),
+            "fold" => return context.primitive_fold(),
+            "from-char-codes" => context.primitive_string_from_char_codes(),
             "fun" => context.primitive_fun(runtime),
-            "let" => context.primitive_let(runtime),
             "get-item" => context.primitive_get_item(),
             "get-key" => context.primitive_get_key(),
-            "loop" => context.primitive_loop(runtime),
-            "match" => context.primitive_match(runtime),
+            "insert" => context.primitive_insert(),
+            "is-type" => context.primitive_is_type(),
+            "join" => context.primitive_string_join(),
+            "keys" => context.primitive_keys(),
+            "length" => context.primitive_length(),
+            "let" => context.primitive_let(runtime),
+            "link" => context.primitive_link(runtime),
+            "list-dir" => context.primitive_list_dir(runtime),
+            "loop" => return context.primitive_loop(runtime),
+            "lowercase" => context.primitive_string_lowercase(),
+            "map" => return context.primitive_map(),
+            "match" => return context.primitive_match(runtime),
+            "merge" => context.primitive_merge(),
             "mod" => context.primitive_numbers_modulo(),
+            "monitor" => context.primitive_monitor(runtime),
+            "now" => Ok(context.primitive_now(runtime)),
+            "number-to-string" => context.primitive_number_to_string(),
             "panic" => context.primitive_panic(),
             "print" => Ok(context.primitive_print(runtime)),
-            "repeat" => context.primitive_repeat(runtime),
-            "run" => context.primitive_run(runtime),
-            "use" => context.primitive_use(runtime),
-            "wait" => context.primitive_wait(runtime),
+            "print-error" => Ok(context.primitive_print_error(runtime)),
+            "push" => context.primitive_push(),
+            "random-int" => context.primitive_random_int(runtime),
+            "read-file" => context.primitive_read_file(runtime),
+            "read-line" => Ok(context.primitive_read_line(runtime)),
+            "receive" => return context.primitive_receive(runtime),
+            "receive-message" => return context.primitive_receive_message(runtime),
+            "remove" => context.primitive_remove(),
+            "remove-file" => context.primitive_remove_file(runtime),
+            "repeat" => return context.primitive_repeat(runtime),
+            "replace" => context.primitive_string_replace(),
+            "reverse" => context.primitive_reverse(),
+            "round" => context.primitive_numbers_round(),
+            "run" => return context.primitive_run(runtime),
+            "select" => return context.primitive_select(runtime),
+            "self" => context.primitive_self(runtime),
+            "send" => return context.primitive_send(runtime),
+            "send-message" => context.primitive_send_message(runtime),
+            "slice" => context.primitive_slice(),
+            "sort" => context.primitive_sort(),
+            "spawn" => context.primitive_spawn(runtime),
+            "spawn-actor" => context.primitive_spawn_actor(runtime),
+            "spawn-process" => context.primitive_spawn_process(runtime),
+            "split" => context.primitive_string_split(),
+            "starts-with?" => context.primitive_string_starts_with(),
+            "string-to-number" => context.primitive_string_to_number(),
+            "string-to-symbol" => context.primitive_string_to_symbol(),
+            "supervise" => context.primitive_supervise(runtime),
+            "symbol-to-string" => context.primitive_symbol_to_string(),
+            "to-char-codes" => context.primitive_string_to_char_codes(),
+            "to-float" => context.primitive_numbers_to_float(),
+            "trim" => context.primitive_string_trim(),
+            "try" => return context.primitive_try(),
+            "typed" => context.primitive_typed(),
+            "uppercase" => context.primitive_string_uppercase(),
+            "use" => return context.primitive_use(runtime),
+            "values" => context.primitive_values(),
+            "wait" => return context.primitive_wait(runtime),
+            "write-file" => context.primitive_write_file(runtime),
             _ => Err(wrong_usage(format!("Unknown primitive {}.", name))),
-        }
+        }?;
+        Ok(Call::Returned(context))
     }
 
     fn primitive_export_all(mut self) -> Self {
         self.funs = self
             .funs
-            .into_iter()
-            .map(|(name, mut fun)| {
-                fun.export_level += 2;
-                (name, fun)
+            .iter()
+            .map(|fun| Fun {
+                export_level: fun.export_level + 2,
+                ..fun.clone()
             })
             .collect();
         self.dot = Expr::unit();
@@ -145,11 +222,13 @@ impl Context {
             .get_symbol("name")
             .needed("fun needs a :name.")?
             .needs_symbol("fun :name needs to be a symbol.")?;
-        let export_level =
-            args.get_symbol("export-level")
-                .unwrap_or(Expr::Number(0))
-                .needs_number("fun :export-level needs to be a number.")? as u16
-                + 1;
+        let export_level = args
+            .get_symbol("export-level")
+            .unwrap_or(Expr::Number(Number::Int(0.into())))
+            .needs_int("fun :export-level needs to be an integer.")?
+            .to_u16()
+            .needed("fun :export-level is too big.")?
+            + 1;
         let docs = args
             .get_symbol("docs")
             .and_then(|docs| docs.clone().as_string());
@@ -158,45 +237,38 @@ impl Context {
             .needed("fun needs a :body.")?
             .clone()
             .needs_code("fun :body needs to be code.")?;
+        let guard = match args.get_symbol("guard") {
+            Some(guard) => {
+                let (scope, body) = guard.needs_code("fun :guard needs to be code.")?;
+                Some(Guard {
+                    scope: Rc::new(*scope),
+                    body,
+                })
+            }
+            None => None,
+        };
 
         let fun = Fun {
             name: name.clone(),
             docs,
-            body: FunBody::Code {
-                scope: Rc::new(*scope),
-                body: Rc::new(body),
-            },
+            overloads: vec![Overload {
+                guard,
+                body: FunBody::Code {
+                    scope: Rc::new(*scope),
+                    body,
+                },
+            }],
             export_level,
         };
         self.dot = Expr::unit();
-        self.funs.insert(name.clone(), fun);
-        runtime.log(&format!(
-            "Defined function {:?}. Known funs: {:?}",
-            &name,
-            self.funs.keys().collect::<Vec<_>>()
-        ));
-        Ok(self)
-    }
-
-    fn primitive_get_item(mut self) -> RunResult {
-        let (list, index) = self
-            .dot
-            .needs_list("get-item needs a list.")?
-            .needs_two_items("get-item needs a list with two items.")?;
-        let list = list.needs_list("get-item needs a list as the first argument.")?;
-        let index = index.as_number().unwrap();
-        self.dot = list[index as usize].clone();
-        Ok(self)
-    }
-
-    fn primitive_get_key(mut self) -> RunResult {
-        let (map, key) = self
-            .dot
-            .needs_list("get-key needs list.")?
-            .needs_two_items("get-key needs a list with two items.")?;
-        let map = map.needs_map("get-key needs a map as the first argument.")?;
-        // TODO: Return Maybe.
-        self.dot = map.get(&key).expect("key not found.").clone();
+        self.funs.define(fun);
+        runtime.log(|| {
+            format!(
+                "Defined function {:?}. Known funs: {:?}",
+                &name,
+                self.funs.names().collect::<Vec<_>>()
+            )
+        });
         Ok(self)
     }
 
@@ -206,171 +278,50 @@ impl Context {
             .get_symbol("name")
             .needed("let needs a :name.")?
             .clone();
-        let export_level =
-            args.get_symbol("export-level")
-                .unwrap_or(Expr::Number(0))
-                .needs_number("let :export-level needs to be a number.")? as u16
-                + 1;
+        let export_level = args
+            .get_symbol("export-level")
+            .unwrap_or(Expr::Number(Number::Int(0.into())))
+            .needs_int("let :export-level needs to be an integer.")?
+            .to_u16()
+            .needed("let :export-level is too big.")?
+            + 1;
         let docs = args
             .get_symbol("docs")
             .and_then(|docs| docs.clone().as_string());
         let value = args.get_symbol("value").needed("let needs a :value.")?;
 
-        let mut definitions = HashMap::new();
-        Self::let_helper(&name, &value, &mut definitions);
+        let pattern = let_pattern(&name)?;
+        let definitions = match_pattern(&pattern, &value).map_err(|mismatch| {
+            wrong_usage(format!(
+                "let can't destructure {} into {}: {}",
+                value, name, mismatch
+            ))
+        })?;
 
         for (name, value) in definitions {
-            let fun = Fun {
-                name: name.clone(),
-                docs: docs.clone(),
-                body: FunBody::Value(Rc::new(value)),
+            let fun = Fun::new(
+                name.clone(),
+                docs.clone(),
+                FunBody::Value(Rc::new(value)),
                 export_level,
-            };
-            self.funs.insert(name.clone(), fun);
-            runtime.log(&format!(
-                "Defined function {:?}. Known funs: {:?}",
-                &name,
-                self.funs.keys().collect::<Vec<_>>()
-            ));
+            );
+            self.funs.define(fun);
+            runtime.log(|| {
+                format!(
+                    "Defined function {:?}. Known funs: {:?}",
+                    &name,
+                    self.funs.names().collect::<Vec<_>>()
+                )
+            });
         }
         self.dot = Expr::unit();
         Ok(self)
     }
-    fn let_helper(name: &Expr, value: &Expr, out: &mut HashMap<String, Expr>) {
-        // TODO: This still panics.
-        match name {
-            Expr::Symbol(name) => {
-                out.insert(name.clone(), value.clone());
-            }
-            Expr::Map(name_map) => {
-                let value_map = value.clone().as_map().unwrap();
-                for (key, name) in name_map {
-                    Self::let_helper(name, value_map.get(&key).unwrap(), out);
-                }
-            }
-            Expr::List(name_list) => {
-                let value_list = value.clone().as_list().unwrap();
-                if name_list.len() != value_list.len() {
-                    panic!("List has different length.");
-                }
-                for (name, value) in name_list.into_iter().zip(value_list.iter()) {
-                    Self::let_helper(name, value, out);
-                }
-            }
-            _ => panic!("Invalid match data on left side of let."),
-        };
-    }
 
-    fn primitive_loop(self, runtime: &mut Runtime) -> RunResult {
+    fn primitive_loop(self, runtime: &mut Runtime) -> Result<Call, Expr> {
         let (scope, body) = self.dot.needs_code("loop needs code.")?;
         let context = scope.next(runtime, Expr::unit());
-        loop {
-            context.clone().run(runtime, body.clone())?;
-        }
-    }
-
-    fn primitive_match(self, runtime: &mut Runtime) -> RunResult {
-        let list = self.dot.needs_list("match needs a list.")?;
-        {
-            // Usage checks.
-            if list.len() < 3 {
-                return Err(wrong_usage(
-                        "match needs a list with at least 3 items – the value, a pattern, and some code."
-                    ));
-            }
-            if list.len() % 2 == 0 {
-                return Err(wrong_usage("match needs a list with an odd number of items – the value, and then in turn patterns and code."));
-            }
-            let mut i = 2;
-            while i < list.len() {
-                list[i]
-                    .clone()
-                    .needs_code("match needs a value, and then in turn patterns and code.")?;
-                i += 2;
-            }
-        }
-
-        let value = list[0].clone();
-        for mut chunk in &list.into_iter().skip(1).chunks(2) {
-            let (condition, code) = (chunk.next().unwrap(), chunk.next().unwrap());
-            let bindings = match Self::match_helper(&condition, &value) {
-                Some(bindings) => bindings,
-                None => continue,
-            };
-            let (scope, body) = code.as_code().expect("checked above");
-            let mut context = scope.next(runtime, Expr::unit());
-            for (key, value) in bindings {
-                context.funs.insert(
-                    key.clone(),
-                    Fun {
-                        name: key,
-                        docs: None,
-                        body: FunBody::Value(Rc::new(value)),
-                        export_level: 0,
-                    },
-                );
-            }
-            return context.run(runtime, body.clone());
-        }
-        Err(wrong_usage("no condition matched"))
-    }
-    fn match_helper(left: &Expr, right: &Expr) -> Option<HashMap<String, Expr>> {
-        fn literal_match<T: Eq>(left: &T, right: &T) -> Option<HashMap<String, Expr>> {
-            if left == right {
-                Some(HashMap::new())
-            } else {
-                None
-            }
-        }
-        match left {
-            Expr::Number(_) => literal_match(left, right),
-            Expr::String(_) => literal_match(left, right),
-            Expr::Symbol(symbol) => {
-                if symbol == "_" {
-                    Some(HashMap::new())
-                } else if symbol.starts_with("?") {
-                    let mut map = HashMap::new();
-                    map.insert(symbol[1..].to_string(), right.clone());
-                    Some(map)
-                } else {
-                    literal_match(left, right)
-                }
-            }
-            Expr::Map(left_map) => {
-                let mut unified = HashMap::new();
-                let right_map = right.clone().as_map()?;
-                for (key, left_value) in left_map {
-                    let bindings = Self::match_helper(left_value, right_map.get(&key)?)?;
-                    for (name, value) in bindings {
-                        if let Some(expected_value) = unified.get(&name) {
-                            literal_match(&value, expected_value)?;
-                        } else {
-                            unified.insert(name, value);
-                        }
-                    }
-                }
-                Some(unified)
-            }
-            Expr::List(left_list) => {
-                let mut unified = HashMap::new();
-                let right_list = right.clone().as_list()?;
-                if left_list.len() != right_list.len() {
-                    return None;
-                }
-                for (left, right) in left_list.into_iter().zip(right_list.iter()) {
-                    let bindings = Self::match_helper(left, right)?;
-                    for (name, value) in bindings {
-                        if let Some(expected_value) = unified.get(&name) {
-                            literal_match(&value, expected_value)?;
-                        } else {
-                            unified.insert(name, value);
-                        }
-                    }
-                }
-                Some(unified)
-            }
-            Expr::Code { scope: _, asts: _ } => literal_match(left, right),
-        }
+        Ok(Call::Task(Box::new(Loop { context, body })))
     }
 
     fn primitive_numbers_add(mut self) -> RunResult {
@@ -378,7 +329,7 @@ impl Context {
             .dot
             .needs_list_of_numbers("+ needs a list of numbers.")?
             .into_iter()
-            .fold(0, |a, b| a + b);
+            .sum::<Number>();
         self.dot = Expr::Number(sum);
         Ok(self)
     }
@@ -394,7 +345,7 @@ impl Context {
             .dot
             .needs_list_of_numbers("* needs a list of numbers.")?
             .into_iter()
-            .fold(1, |a, b| a * b);
+            .product::<Number>();
         self.dot = Expr::Number(product);
         Ok(self)
     }
@@ -402,14 +353,62 @@ impl Context {
         let (first, second) = self
             .dot
             .needs_pair_of_numbers("/ needs a list of two numbers.")?;
-        self.dot = Expr::Number(first / second);
+        self.dot = Expr::Number(first.checked_div(second).ok_or_else(division_by_zero)?);
         Ok(self)
     }
     fn primitive_numbers_modulo(mut self) -> RunResult {
         let (first, second) = self
             .dot
             .needs_pair_of_numbers("mod needs a list of two numbers.")?;
-        self.dot = Expr::Number(first % second);
+        self.dot = Expr::Number(first.checked_rem(second).ok_or_else(division_by_zero)?);
+        Ok(self)
+    }
+    fn primitive_numbers_floor(mut self) -> RunResult {
+        let number = self.dot.needs_number("floor needs a number.")?;
+        self.dot = Expr::Number(number.floor().needed("floor needs a finite number.")?);
+        Ok(self)
+    }
+    fn primitive_numbers_round(mut self) -> RunResult {
+        let number = self.dot.needs_number("round needs a number.")?;
+        self.dot = Expr::Number(number.round().needed("round needs a finite number.")?);
+        Ok(self)
+    }
+    fn primitive_numbers_to_float(mut self) -> RunResult {
+        let number = self.dot.needs_number("to-float needs a number.")?;
+        self.dot = Expr::Number(number.into_float());
+        Ok(self)
+    }
+
+    fn primitive_compare(mut self) -> RunResult {
+        let (first, second) = self.dot.needs_pair("compare needs a list of two values.")?;
+        self.dot = Expr::Symbol(
+            match first.cmp(&second) {
+                Ordering::Less => "less",
+                Ordering::Equal => "equal",
+                Ordering::Greater => "greater",
+            }
+            .into(),
+        );
+        Ok(self)
+    }
+    fn primitive_is_equal(mut self) -> RunResult {
+        let (first, second) = self.dot.needs_pair("= needs a list of two values.")?;
+        self.dot = Expr::bool(first.cmp(&second) == Ordering::Equal);
+        Ok(self)
+    }
+    /// Checks whether a pair of values is ordered in a certain way, like `<`.
+    /// Numbers are compared by their value, so `1` and `1.0` are neither less
+    /// nor greater than each other. Other values are compared like in
+    /// `compare`.
+    fn primitive_is_ordered(mut self, name: &str, orderings: &[Ordering]) -> RunResult {
+        let (first, second) = self
+            .dot
+            .needs_pair(format!("{} needs a list of two values.", name))?;
+        let ordering = match (&first, &second) {
+            (Expr::Number(first), Expr::Number(second)) => first.compare(second),
+            _ => first.cmp(&second),
+        };
+        self.dot = Expr::bool(orderings.contains(&ordering));
         Ok(self)
     }
 
@@ -422,40 +421,113 @@ impl Context {
         self
     }
 
-    fn primitive_repeat(self, runtime: &mut Runtime) -> RunResult {
+    fn primitive_repeat(self, runtime: &mut Runtime) -> Result<Call, Expr> {
         let (code, n) = self
             .dot
             .needs_list("repeat needs a list with code and a number.")?
             .needs_two_items("repeat needs two arguments – code and a number.")?;
         let (scope, body) = code.needs_code("run needs code.")?;
-        let n = n.needs_number("run needs a number of how many times to repeat.")?;
+        let n = n.needs_int("run needs an integer of how many times to repeat.")?;
+        let n = if n.is_negative() {
+            0
+        } else {
+            n.to_u64().needed("can't repeat that many times.")?
+        };
         let context = scope.next(runtime, Expr::unit());
-        for _ in 0..n {
-            context.clone().run(runtime, body.clone())?;
-        }
-        Ok(context)
+        Ok(Call::Task(Box::new(Repeat {
+            context: Some(context),
+            body,
+            remaining: n,
+        })))
     }
 
-    fn primitive_run(self, runtime: &mut Runtime) -> RunResult {
+    fn primitive_run(self, runtime: &mut Runtime) -> Result<Call, Expr> {
         let (scope, body) = self.dot.needs_code("run needs code.")?;
-        scope.next(runtime, Expr::unit()).run(runtime, body.clone())
+        Ok(Call::Continue(scope.next(runtime, Expr::unit()), body))
     }
 
-    fn primitive_use(mut self, runtime: &mut Runtime) -> RunResult {
+    /// Parses a string as code and runs it in the current context.
+    fn primitive_eval(self, runtime: &mut Runtime) -> Result<Call, Expr> {
+        let source = self.dot.clone().needs_string("eval needs a string.")?;
+        let asts = Ast::parse_all(&source).map_err(|errors| {
+            let first = errors.into_iter().next().expect("Parsing failed.");
+            error("parse-error", first.in_file("eval").to_string())
+        })?;
+        let context = self.next(runtime, Expr::unit()).in_source("eval");
+        Ok(Call::Continue(context, Chunk::compile(asts)))
+    }
+
+    fn primitive_use(self, runtime: &mut Runtime) -> Result<Call, Expr> {
         let (scope, body) = self.dot.clone().needs_code("use needs code")?;
-        let result = scope.next(runtime, Expr::unit()).run(runtime, body)?;
-        for (name, fun) in result.funs {
-            self.funs.insert(name, fun);
+        let used = scope.next(runtime, Expr::unit());
+        Ok(Call::Task(Box::new(Use {
+            context: Some(self),
+            used: Some((used, body)),
+        })))
+    }
+}
+
+struct Loop {
+    context: Context,
+    body: Rc<Chunk>,
+}
+impl Task for Loop {
+    fn start(&mut self, _runtime: &mut Runtime) -> Result<Step, Expr> {
+        Ok(Step::Run(self.context.clone(), self.body.clone()))
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        result?;
+        self.start(runtime)
+    }
+}
+
+struct Repeat {
+    context: Option<Context>,
+    body: Rc<Chunk>,
+    remaining: u64,
+}
+impl Task for Repeat {
+    fn start(&mut self, _runtime: &mut Runtime) -> Result<Step, Expr> {
+        let context = self.context.as_ref().expect("repeat isn't done yet.");
+        if self.remaining == 0 {
+            return Ok(Step::Done(self.context.take().unwrap()));
         }
-        Ok(self)
+        self.remaining -= 1;
+        Ok(Step::Run(context.clone(), self.body.clone()))
+    }
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        result?;
+        self.start(runtime)
     }
+}
 
-    fn primitive_wait(self, runtime: &mut Runtime) -> RunResult {
-        let seconds = self.dot.clone().needs_number("wait needs a number.")?;
-        if seconds < 0 {
-            return Err(wrong_usage("can't wait a negative number of seconds."));
+struct Use {
+    context: Option<Context>,
+    /// The code to use, until it runs.
+    used: Option<(Context, Rc<Chunk>)>,
+}
+impl Task for Use {
+    fn start(&mut self, _runtime: &mut Runtime) -> Result<Step, Expr> {
+        let (context, body) = self.used.take().expect("use runs its code once.");
+        Ok(Step::Run(context, body))
+    }
+    fn resume(&mut self, _runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr> {
+        let result = result?;
+        let mut context = self.context.take().expect("use is done once.");
+        for fun in result.funs.iter() {
+            context.funs.define(fun.clone());
         }
-        runtime.wait(seconds as u64);
-        Ok(self)
+        Ok(Step::Done(context))
+    }
+}
+
+/// Shortens the display of a value to a length that fits into a stack trace.
+fn summarize(dot: &Expr) -> String {
+    const MAX_LENGTH: usize = 40;
+    let string = dot.to_string();
+    if string.chars().count() <= MAX_LENGTH {
+        return string;
     }
+    let shortened = string.chars().take(MAX_LENGTH - 1).collect::<String>();
+    format!("{}…", shortened)
 }
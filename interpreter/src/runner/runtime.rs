@@ -1,54 +1,200 @@
+use super::{compile::Chunk, error, fibers::Scheduler, host::*, system::Capabilities};
 use crate::ast::*;
-use im::HashMap;
+use crate::number::Number;
+use im::{HashSet, OrdMap, Vector};
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
 use std::rc::Rc;
 
-#[derive(Default)]
 pub struct Runtime {
     depth: u64,
+    max_depth: u64,
+    /// How many runs of code are nested on the native stack, like guards that
+    /// run while choosing an overload.
+    nested_runs: u64,
     next_context_id: u64,
+    /// The function calls that the current panic passed through, innermost
+    /// first. It's reset when the panic gets handled.
+    trace: Vec<Frame>,
+    pub(super) scheduler: Scheduler,
+    pub(super) host: Box<dyn Host>,
+    pub(super) capabilities: Capabilities,
+}
+
+/// A function call, as shown in stack traces.
+#[derive(Clone)]
+pub struct Frame {
+    pub name: String,
+    /// The source file of the code that called the function.
+    pub source: Rc<str>,
+    pub span: Span,
+    /// A shortened version of the dot that the function got.
+    pub dot: String,
+    /// How many times the same call happened right before this one in tail
+    /// position. Those calls are not recorded separately.
+    pub repeated: u64,
+}
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} in {}, called with {}",
+            self.source, self.span.start, self.name, self.dot
+        )
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            depth: 0,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            nested_runs: 0,
+            next_context_id: 0,
+            trace: vec![],
+            scheduler: Scheduler::default(),
+            host: Box::new(ProcessHost::default()),
+            capabilities: Capabilities::default(),
+        }
+    }
 }
 impl Runtime {
+    pub const DEFAULT_MAX_DEPTH: u64 = 10_000;
+    /// How many runs of code can be nested on the native stack. Calls don't
+    /// use the native stack, but guards do.
+    pub const MAX_NESTED_RUNS: u64 = 100;
+
+    pub fn with_max_depth(self, max_depth: u64) -> Self {
+        Self { max_depth, ..self }
+    }
+    pub fn with_host(self, host: impl Host + 'static) -> Self {
+        Self {
+            host: Box::new(host),
+            ..self
+        }
+    }
+    /// Grants access to files and processes. Without it, programs can't use
+    /// any of them.
+    pub fn with_capabilities(self, capabilities: Capabilities) -> Self {
+        Self {
+            capabilities,
+            ..self
+        }
+    }
+    /// Runs fibers that can continue in a random order that only depends on
+    /// the seed, instead of in the order in which they became ready.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.scheduler.seed(seed);
+        self
+    }
+
     pub fn next_context_id(&mut self) -> u64 {
         let id = self.next_context_id;
         self.next_context_id += 1;
         id
     }
-    pub fn log(&mut self, msg: &str) {
-        // println!("{}{}", "  ".repeat(self.depth as usize), msg);
+    /// Logs a message. It's only created if logging is enabled, so that
+    /// formatting it doesn't slow down running code.
+    pub fn log(&mut self, _msg: impl FnOnce() -> String) {
+        // println!("{}{}", "  ".repeat(self.depth as usize), _msg());
     }
 
-    pub fn depth_increase(&mut self) {
+    /// Goes one level deeper. Panics with a `stack-overflow` error instead if
+    /// that's deeper than the maximum depth.
+    pub fn descend(&mut self) -> Result<(), Expr> {
+        if self.depth >= self.max_depth {
+            return Err(error(
+                "stack-overflow",
+                format!(
+                    "The maximum depth of {} nested calls was exceeded.",
+                    self.max_depth
+                ),
+            ));
+        }
         self.depth += 1;
+        Ok(())
     }
-    pub fn depth_decrease(&mut self) {
+    pub fn ascend(&mut self) {
         self.depth -= 1;
     }
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+    /// Goes back to an earlier depth, for example after a panic left some
+    /// levels without ascending.
+    pub fn restore_depth(&mut self, depth: u64) {
+        self.depth = depth;
+    }
+
+    /// Starts a run of code that nests on the native stack. Panics with a
+    /// `stack-overflow` error instead if too many runs are nested already.
+    pub(super) fn enter_nested_run(&mut self) -> Result<(), Expr> {
+        if self.nested_runs >= Self::MAX_NESTED_RUNS {
+            return Err(error(
+                "stack-overflow",
+                format!(
+                    "Guards can be nested at most {} levels deep.",
+                    Self::MAX_NESTED_RUNS
+                ),
+            ));
+        }
+        self.nested_runs += 1;
+        Ok(())
+    }
+    pub(super) fn leave_nested_run(&mut self) {
+        self.nested_runs -= 1;
+    }
+
+    /// Records that a panic left a function call.
+    pub fn unwind(&mut self, frame: Frame) {
+        self.trace.push(frame);
+    }
+    /// Returns the calls that the current panic passed through and forgets
+    /// them, for example because the panic got handled.
+    pub fn take_trace(&mut self) -> Vec<Frame> {
+        std::mem::take(&mut self.trace)
+    }
+    pub fn restore_trace(&mut self, trace: Vec<Frame>) {
+        self.trace = trace;
+    }
 
     pub fn print(&mut self, expr: &Expr) {
-        println!("🌮> {}", expr);
+        self.host.print(&format!("🌮> {}", expr));
     }
-    pub fn wait(&mut self, seconds: u64) {
-        std::thread::sleep(std::time::Duration::new(seconds, 0));
+    pub fn print_error(&mut self, expr: &Expr) {
+        self.host.print_error(&format!("🌮> {}", expr));
     }
 }
 
 #[derive(Clone, Eq, PartialEq)]
 pub enum Expr {
-    Number(i64),
+    Number(Number),
     String(String),
     Symbol(String),
-    Map(HashMap<Expr, Expr>),
+    /// Maps are sorted by their keys, so iterating over them is deterministic.
+    Map(OrdMap<Expr, Expr>),
     List(Vec<Expr>),
-    Code { scope: Box<Context>, asts: Asts },
+    Code {
+        scope: Box<Context>,
+        chunk: Rc<Chunk>,
+    },
 }
 
 impl Expr {
     pub fn unit() -> Self {
         Self::Symbol("".into())
     }
+    pub fn bool(value: bool) -> Self {
+        Self::Symbol(if value { "true" } else { "false" }.into())
+    }
+    pub fn int(int: usize) -> Self {
+        Self::Number(Number::Int((int as i64).into()))
+    }
 }
 
 impl Hash for Expr {
@@ -57,22 +203,69 @@ impl Hash for Expr {
             Expr::Number(number) => number.hash(state),
             Expr::String(string) => string.hash(state),
             Expr::Symbol(symbol) => symbol.hash(state),
-            Expr::Map(map) => {
-                let mut h = 0;
-
-                for element in map.iter() {
-                    let mut hasher = DefaultHasher::new();
-                    element.hash(&mut hasher);
-                    h ^= hasher.finish();
-                }
+            Expr::Map(map) => map.hash(state),
+            Expr::List(list) => list.hash(state),
+            Expr::Code { scope, chunk } => (scope.id, chunk).hash(state),
+        }
+    }
+}
 
-                state.write_u64(h);
+/// A total order over all values, so that any values can be sorted. Values of
+/// different types are ordered numbers, strings, symbols, lists, maps, code.
+/// Lists are compared item by item and maps entry by entry.
+impl Ord for Expr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        fn rank(expr: &Expr) -> u8 {
+            match expr {
+                Expr::Number(_) => 0,
+                Expr::String(_) => 1,
+                Expr::Symbol(_) => 2,
+                Expr::List(_) => 3,
+                Expr::Map(_) => 4,
+                Expr::Code { .. } => 5,
             }
-            Expr::List(list) => list.hash(state),
-            Expr::Code { scope, asts } => (scope.id, asts).hash(state),
+        }
+        match (self, other) {
+            (Expr::Number(a), Expr::Number(b)) => a.cmp(b),
+            (Expr::String(a), Expr::String(b)) => a.cmp(b),
+            (Expr::Symbol(a), Expr::Symbol(b)) => a.cmp(b),
+            (Expr::List(a), Expr::List(b)) => a.cmp(b),
+            (Expr::Map(a), Expr::Map(b)) => a.cmp(b),
+            (
+                Expr::Code {
+                    scope: a_scope,
+                    chunk: a_chunk,
+                },
+                Expr::Code {
+                    scope: b_scope,
+                    chunk: b_chunk,
+                },
+            ) => a_scope.id.cmp(&b_scope.id).then_with(|| {
+                let (a_asts, b_asts) = (&a_chunk.asts, &b_chunk.asts);
+                if a_asts == b_asts {
+                    Ordering::Equal
+                } else {
+                    // Code has no natural order, but formatting it gives a
+                    // deterministic one.
+                    format_code(a_asts)
+                        .cmp(&format_code(b_asts))
+                        .then_with(|| hash_of(a_asts).cmp(&hash_of(b_asts)))
+                }
+            }),
+            _ => rank(self).cmp(&rank(other)),
         }
     }
 }
+impl PartialOrd for Expr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -93,12 +286,12 @@ impl fmt::Display for Expr {
                 "({})",
                 itertools::join(list.iter().map(|item| format!("{}", item)), ", ")
             ),
-            Expr::Code { scope: _, asts } => {
+            Expr::Code { scope: _, chunk } => {
                 write!(f, "[")?;
                 write!(
                     f,
                     "{}, ",
-                    itertools::join(asts.iter().map(|ast| format!("{}", ast)), " ")
+                    itertools::join(chunk.asts.iter().map(|ast| format!("{}", ast)), " ")
                 )?;
                 write!(f, "]")?;
                 Ok(())
@@ -110,7 +303,9 @@ impl fmt::Display for Expr {
 #[derive(Clone)]
 pub struct Context {
     id: u64,
-    pub funs: HashMap<String, Fun>,
+    /// The file that the code running in this context comes from.
+    pub source: Rc<str>,
+    pub funs: Funs,
     pub dot: Expr,
 }
 impl Eq for Context {}
@@ -121,18 +316,16 @@ impl PartialEq for Context {
 }
 impl Context {
     pub fn root(runtime: &mut Runtime) -> Self {
-        let mut funs = HashMap::new();
-        funs.insert(
+        let mut funs = Funs::default();
+        funs.insert(Fun::new(
             "✨".into(),
-            Fun {
-                name: "✨".into(),
-                docs: Some("The primitive fun.".into()),
-                body: FunBody::Primitive,
-                export_level: 0,
-            },
-        );
+            Some("The primitive fun.".into()),
+            FunBody::Primitive,
+            0,
+        ));
         Self {
             id: runtime.next_context_id(),
+            source: "<unknown>".into(),
             funs,
             dot: Expr::unit(),
         }
@@ -141,22 +334,192 @@ impl Context {
     pub fn next(self, runtime: &mut Runtime, dot: Expr) -> Self {
         Self {
             id: runtime.next_context_id(),
+            source: self.source,
             funs: self.funs,
             dot,
         }
     }
+    /// Turns this context into the next one, like `next`.
+    pub fn advance(&mut self, runtime: &mut Runtime, dot: Expr) {
+        self.id = runtime.next_context_id();
+        self.dot = dot;
+    }
+
+    /// Returns a context for running code from the given file.
+    pub fn in_source(self, source: &str) -> Self {
+        Self {
+            source: source.into(),
+            ..self
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Fun {
     pub name: String,
     pub docs: Option<String>,
-    pub body: FunBody,
+    /// The overloads in the order they were defined. Calling the fun runs the
+    /// first overload whose guard accepts the dot.
+    pub overloads: Vec<Overload>,
     pub export_level: u16,
 }
+#[derive(Clone)]
+pub struct Overload {
+    /// Code that gets the dot and returns `:true` or `:false`. Overloads
+    /// without a guard accept everything.
+    pub guard: Option<Guard>,
+    pub body: FunBody,
+}
+#[derive(Clone)]
+pub struct Guard {
+    pub scope: Rc<Context>,
+    pub body: Rc<Chunk>,
+}
+impl Fun {
+    pub fn new(name: String, docs: Option<String>, body: FunBody, export_level: u16) -> Self {
+        Self {
+            name,
+            docs,
+            overloads: vec![Overload { guard: None, body }],
+            export_level,
+        }
+    }
+
+    /// Whether this fun is a value defined using `let` rather than a function.
+    pub fn is_value(&self) -> bool {
+        self.overloads
+            .iter()
+            .any(|overload| matches!(overload.body, FunBody::Value(_)))
+    }
+}
+
+/// A number that stands for the name of a fun. Names in code get their slots
+/// when the code is compiled, so calls look up funs by index instead of
+/// hashing and comparing names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Slot(u32);
+
+/// The names that have a slot, indexed by their slot, and the slots of the
+/// names.
+#[derive(Default)]
+struct Slots {
+    names: Vec<Rc<str>>,
+    slots: std::collections::HashMap<Rc<str>, Slot>,
+}
+thread_local! {
+    /// Code values can't leave the thread they were compiled on, so their
+    /// slots always stand for the same names.
+    static SLOTS: RefCell<Slots> = RefCell::default();
+}
+impl Slot {
+    /// Returns the slot of the name. Names get a slot the first time they're
+    /// used.
+    pub fn of(name: &str) -> Self {
+        SLOTS.with(|slots| {
+            let Slots { names, slots } = &mut *slots.borrow_mut();
+            if let Some(slot) = slots.get(name) {
+                return *slot;
+            }
+            let slot = Slot(u32::try_from(names.len()).expect("There are too many names."));
+            let name = Rc::<str>::from(name);
+            names.push(name.clone());
+            slots.insert(name, slot);
+            slot
+        })
+    }
+    pub fn name(self) -> Rc<str> {
+        SLOTS.with(|slots| slots.borrow().names[self.index()].clone())
+    }
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The funs that are available in a context, indexed by their slot. The slots
+/// of the funs that get exported when a call returns are also kept
+/// separately, so that returning doesn't have to look at all funs.
+#[derive(Clone, Default)]
+pub struct Funs {
+    funs: Vector<Option<Rc<Fun>>>,
+    exported: HashSet<Slot>,
+}
+impl Funs {
+    pub fn get(&self, slot: Slot) -> Option<&Fun> {
+        self.funs.get(slot.index())?.as_deref()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Fun> {
+        self.funs.iter().flatten().map(|fun| &**fun)
+    }
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|fun| &fun.name)
+    }
+    /// The funs with an export level of at least 1.
+    pub fn exported(&self) -> impl Iterator<Item = &Fun> {
+        self.exported.iter().filter_map(move |slot| self.get(*slot))
+    }
+    pub fn exports_anything(&self) -> bool {
+        !self.exported.is_empty()
+    }
+
+    /// Adds a fun, replacing an existing one with the same name.
+    pub fn insert(&mut self, fun: Fun) {
+        self.insert_at(Slot::of(&fun.name), fun);
+    }
+    /// Adds a fun whose slot is already known.
+    pub(super) fn insert_at(&mut self, slot: Slot, fun: Fun) {
+        if fun.export_level >= 1 {
+            self.exported.insert(slot);
+        } else {
+            self.exported.remove(&slot);
+        }
+        while self.funs.len() <= slot.index() {
+            self.funs.push_back(None);
+        }
+        self.funs.set(slot.index(), Some(Rc::new(fun)));
+    }
+
+    /// Adds a fun. Values replace existing funs with the same name, but
+    /// functions only add their overloads to the existing ones: An overload
+    /// with the same guard as an existing one replaces it, other overloads are
+    /// tried after the existing ones.
+    pub fn define(&mut self, fun: Fun) {
+        let existing = match self.get(Slot::of(&fun.name)) {
+            Some(existing) if !fun.is_value() && !existing.is_value() => existing,
+            _ => return self.insert(fun),
+        };
+        let mut overloads = existing.overloads.clone();
+        for overload in fun.overloads {
+            let guard_body = overload.guard.as_ref().map(|guard| guard.body.clone());
+            match overloads
+                .iter_mut()
+                .find(|it| it.guard.as_ref().map(|guard| guard.body.clone()) == guard_body)
+            {
+                Some(existing) => *existing = overload,
+                None => overloads.push(overload),
+            }
+        }
+        let merged = Fun {
+            name: fun.name,
+            docs: fun.docs.or_else(|| existing.docs.clone()),
+            overloads,
+            export_level: fun.export_level,
+        };
+        self.insert(merged);
+    }
+}
+impl FromIterator<Fun> for Funs {
+    fn from_iter<I: IntoIterator<Item = Fun>>(iter: I) -> Self {
+        let mut funs = Self::default();
+        for fun in iter {
+            funs.insert(fun);
+        }
+        funs
+    }
+}
+
 #[derive(Clone)]
 pub enum FunBody {
     Primitive,
-    Code { scope: Rc<Context>, body: Rc<Asts> },
+    Code { scope: Rc<Context>, body: Rc<Chunk> },
     Value(Rc<Expr>),
 }
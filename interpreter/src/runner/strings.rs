@@ -0,0 +1,203 @@
+use num_traits::ToPrimitive;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{runtime::*, utils::*};
+use crate::ast::parse_number;
+
+// String primitives. Lengths and indices count graphemes, so `"🇩🇪"` has a
+// length of 1 although it consists of two chars.
+impl Context {
+    pub(super) fn primitive_string_length(mut self) -> RunResult {
+        let string = self.dot.needs_string("length needs a string.")?;
+        self.dot = Expr::int(string.graphemes(true).count());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_concat(mut self) -> RunResult {
+        let strings = self
+            .dot
+            .needs_list_of_strings("concat needs a list of strings.")?;
+        self.dot = Expr::String(strings.concat());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_slice(mut self) -> RunResult {
+        let msg = "slice needs a string, a start index and an end index.";
+        let (string, start, end) = self.dot.needs_list(msg)?.needs_three_items(msg)?;
+        let string = string.needs_string(msg)?;
+        let start = start.needs_index(msg)?;
+        let end = end.needs_index(msg)?;
+        let graphemes = string.graphemes(true).collect::<Vec<_>>();
+        if start > end || end > graphemes.len() {
+            return Err(wrong_usage(format!(
+                "slice can't take the graphemes from {} to {} of a string with length {}.",
+                start,
+                end,
+                graphemes.len()
+            )));
+        }
+        self.dot = Expr::String(graphemes[start..end].concat());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_split(mut self) -> RunResult {
+        let msg = "split needs a string and a separator.";
+        let (string, separator) = self.dot.needs_pair_of_strings(msg)?;
+        let parts: Vec<&str> = if separator.is_empty() {
+            string.graphemes(true).collect()
+        } else {
+            string.split(separator.as_str()).collect()
+        };
+        self.dot = Expr::List(
+            parts
+                .into_iter()
+                .map(|part| Expr::String(part.to_string()))
+                .collect(),
+        );
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_join(mut self) -> RunResult {
+        let msg = "join needs a list of strings and a separator.";
+        let (strings, separator) = self.dot.needs_pair(msg)?;
+        let strings = strings.needs_list_of_strings(msg)?;
+        let separator = separator.needs_string(msg)?;
+        self.dot = Expr::String(strings.join(&separator));
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_find(mut self) -> RunResult {
+        let (string, needle) = self
+            .dot
+            .needs_pair_of_strings("find needs a string and a string to search for.")?;
+        // Only matches that start and end between graphemes count, so "e"
+        // isn't found in "é" written as an e and a combining accent.
+        let boundaries = string
+            .grapheme_indices(true)
+            .map(|(index, _)| index)
+            .chain(std::iter::once(string.len()))
+            .collect::<Vec<_>>();
+        let index = boundaries.iter().position(|start| {
+            string[*start..].starts_with(needle.as_str())
+                && boundaries.binary_search(&(start + needle.len())).is_ok()
+        });
+        self.dot = match index {
+            Some(index) => some(Expr::int(index)),
+            None => none(),
+        };
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_replace(mut self) -> RunResult {
+        let msg = "replace needs a string, the string to replace and its replacement.";
+        let (string, from, to) = self.dot.needs_list(msg)?.needs_three_items(msg)?;
+        let string = string.needs_string(msg)?;
+        let from = from.needs_string(msg)?;
+        let to = to.needs_string(msg)?;
+        if from.is_empty() {
+            return Err(wrong_usage("replace can't replace an empty string."));
+        }
+        self.dot = Expr::String(string.replace(&from, &to));
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_uppercase(mut self) -> RunResult {
+        let string = self.dot.needs_string("uppercase needs a string.")?;
+        self.dot = Expr::String(string.to_uppercase());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_lowercase(mut self) -> RunResult {
+        let string = self.dot.needs_string("lowercase needs a string.")?;
+        self.dot = Expr::String(string.to_lowercase());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_trim(mut self) -> RunResult {
+        let string = self.dot.needs_string("trim needs a string.")?;
+        self.dot = Expr::String(string.trim().to_string());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_starts_with(mut self) -> RunResult {
+        let (string, prefix) = self
+            .dot
+            .needs_pair_of_strings("starts-with? needs a string and a prefix.")?;
+        self.dot = Expr::bool(string.starts_with(&prefix));
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_ends_with(mut self) -> RunResult {
+        let (string, suffix) = self
+            .dot
+            .needs_pair_of_strings("ends-with? needs a string and a suffix.")?;
+        self.dot = Expr::bool(string.ends_with(&suffix));
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_to_char_codes(mut self) -> RunResult {
+        let string = self.dot.needs_string("to-char-codes needs a string.")?;
+        self.dot = Expr::List(string.chars().map(|c| Expr::int(c as usize)).collect());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_from_char_codes(mut self) -> RunResult {
+        let msg = "from-char-codes needs a list of char codes.";
+        let string = self
+            .dot
+            .needs_list(msg)?
+            .into_iter()
+            .map(|code| {
+                code.needs_int(msg)?
+                    .to_u32()
+                    .and_then(char::from_u32)
+                    .needed(msg)
+            })
+            .collect::<Result<String, Expr>>()?;
+        self.dot = Expr::String(string);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_number_to_string(mut self) -> RunResult {
+        let number = self.dot.needs_number("number-to-string needs a number.")?;
+        self.dot = Expr::String(number.to_string());
+        Ok(self)
+    }
+
+    /// Parses a number using the same syntax as number literals in code.
+    /// Surrounding whitespace is ignored, but nothing else may come before or
+    /// after the number.
+    pub(super) fn primitive_string_to_number(mut self) -> RunResult {
+        let string = self.dot.needs_string("string-to-number needs a string.")?;
+        self.dot = match parse_number(string.trim()) {
+            Some(number) => some(Expr::Number(number)),
+            None => none(),
+        };
+        Ok(self)
+    }
+
+    pub(super) fn primitive_symbol_to_string(mut self) -> RunResult {
+        let symbol = self.dot.needs_symbol("symbol-to-string needs a symbol.")?;
+        self.dot = Expr::String(symbol);
+        Ok(self)
+    }
+
+    pub(super) fn primitive_string_to_symbol(mut self) -> RunResult {
+        let string = self.dot.needs_string("string-to-symbol needs a string.")?;
+        self.dot = Expr::Symbol(string);
+        Ok(self)
+    }
+}
+
+impl Expr {
+    fn needs_list_of_strings(self, msg: &str) -> Result<Vec<String>, Expr> {
+        self.needs_list(msg)?
+            .into_iter()
+            .map(|string| string.needs_string(msg))
+            .collect()
+    }
+    fn needs_pair_of_strings(self, msg: &str) -> Result<(String, String), Expr> {
+        let (first, second) = self.needs_pair(msg)?;
+        Ok((first.needs_string(msg)?, second.needs_string(msg)?))
+    }
+}
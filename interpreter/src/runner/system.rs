@@ -0,0 +1,254 @@
+use im::OrdMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use super::{runtime::*, utils::*};
+use crate::number::{Int, Number};
+
+// Primitives for the file system and for starting processes. Programs can only
+// use them with the capabilities that the runtime grants, like reading from a
+// directory or running git. By default, nothing is granted, so untrusted
+// programs run sandboxed.
+
+/// What a program may access outside of the runtime. Only files and
+/// processes are guarded: Environment variables, the clock and random numbers
+/// come from the host and are always available.
+#[derive(Clone, Default)]
+pub struct Capabilities {
+    read: Access<PathBuf>,
+    write: Access<PathBuf>,
+    run: Access<String>,
+}
+#[derive(Clone)]
+enum Access<T> {
+    Only(Vec<T>),
+    All,
+}
+impl<T> Default for Access<T> {
+    fn default() -> Self {
+        Access::Only(vec![])
+    }
+}
+impl<T> Access<T> {
+    fn grant(&mut self, item: T) {
+        if let Access::Only(items) = self {
+            items.push(item);
+        }
+    }
+}
+
+impl Capabilities {
+    /// Allows reading the file or everything in the directory.
+    pub fn allow_read(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        self.read.grant(resolve(path.as_ref())?);
+        Ok(self)
+    }
+    pub fn allow_read_all(self) -> Self {
+        Self {
+            read: Access::All,
+            ..self
+        }
+    }
+    /// Allows writing the file or everything in the directory.
+    pub fn allow_write(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        self.write.grant(resolve(path.as_ref())?);
+        Ok(self)
+    }
+    pub fn allow_write_all(self) -> Self {
+        Self {
+            write: Access::All,
+            ..self
+        }
+    }
+    /// Allows running the program. It needs to be started with exactly this
+    /// name.
+    pub fn allow_run(mut self, program: impl Into<String>) -> Self {
+        self.run.grant(program.into());
+        self
+    }
+    pub fn allow_run_all(self) -> Self {
+        Self {
+            run: Access::All,
+            ..self
+        }
+    }
+
+    fn check_path(
+        access: &Access<PathBuf>,
+        path: &str,
+        action: &str,
+        flag: &str,
+    ) -> Result<PathBuf, Expr> {
+        let resolved = resolve(Path::new(path)).map_err(|err| io_error(path, err))?;
+        let allowed = match access {
+            Access::All => true,
+            Access::Only(paths) => paths.iter().any(|allowed| resolved.starts_with(allowed)),
+        };
+        if !allowed {
+            return Err(permission_denied(format!(
+                "{} {} needs --{}.",
+                action, path, flag
+            )));
+        }
+        Ok(resolved)
+    }
+    fn check_read(&self, path: &str) -> Result<PathBuf, Expr> {
+        Self::check_path(&self.read, path, "Reading", "allow-read")
+    }
+    fn check_write(&self, path: &str) -> Result<PathBuf, Expr> {
+        Self::check_path(&self.write, path, "Writing", "allow-write")
+    }
+    fn check_run(&self, program: &str) -> Result<(), Expr> {
+        let allowed = match &self.run {
+            Access::All => true,
+            Access::Only(programs) => programs.iter().any(|allowed| allowed == program),
+        };
+        if !allowed {
+            return Err(permission_denied(format!(
+                "Running {} needs --allow-run.",
+                program
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Turns a path into an absolute one without `.`, `..` or symbolic links, so
+/// that paths can't escape the directories that were granted. The path
+/// doesn't need to exist.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = std::env::current_dir()?;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                resolved = PathBuf::from(component.as_os_str());
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                resolved.push(name);
+                if resolved.symlink_metadata().is_ok() {
+                    resolved = resolved.canonicalize()?;
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+fn permission_denied(msg: String) -> Expr {
+    error("permission-denied", msg)
+}
+fn io_error(path: &str, err: io::Error) -> Expr {
+    let kind = match err.kind() {
+        io::ErrorKind::NotFound => "not-found",
+        io::ErrorKind::AlreadyExists => "already-exists",
+        _ => "io-error",
+    };
+    error(kind, format!("{}: {}", path, err))
+}
+
+impl Context {
+    pub(super) fn primitive_read_file(mut self, runtime: &mut Runtime) -> RunResult {
+        let path = self.dot.needs_string("read-file needs a path.")?;
+        let resolved = runtime.capabilities.check_read(&path)?;
+        let content = std::fs::read_to_string(resolved).map_err(|err| io_error(&path, err))?;
+        self.dot = Expr::String(content);
+        Ok(self)
+    }
+
+    /// Writes a string to a file. If the file already exists, it's replaced.
+    pub(super) fn primitive_write_file(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "write-file needs a path and a string.";
+        let (path, content) = self.dot.needs_pair(msg)?;
+        let path = path.needs_string(msg)?;
+        let content = content.needs_string(msg)?;
+        let resolved = runtime.capabilities.check_write(&path)?;
+        std::fs::write(resolved, content).map_err(|err| io_error(&path, err))?;
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+
+    /// Returns the sorted names of the entries of a directory.
+    pub(super) fn primitive_list_dir(mut self, runtime: &mut Runtime) -> RunResult {
+        let path = self.dot.needs_string("list-dir needs a path.")?;
+        let resolved = runtime.capabilities.check_read(&path)?;
+        let mut names = vec![];
+        for entry in std::fs::read_dir(resolved).map_err(|err| io_error(&path, err))? {
+            let entry = entry.map_err(|err| io_error(&path, err))?;
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        self.dot = Expr::List(names.into_iter().map(Expr::String).collect());
+        Ok(self)
+    }
+
+    pub(super) fn primitive_exists(mut self, runtime: &mut Runtime) -> RunResult {
+        let path = self.dot.needs_string("exists? needs a path.")?;
+        let resolved = runtime.capabilities.check_read(&path)?;
+        self.dot = Expr::bool(resolved.exists());
+        Ok(self)
+    }
+
+    /// Creates a directory and the directories it's in, if they don't exist
+    /// yet.
+    pub(super) fn primitive_create_dir(mut self, runtime: &mut Runtime) -> RunResult {
+        let path = self.dot.needs_string("create-dir needs a path.")?;
+        let resolved = runtime.capabilities.check_write(&path)?;
+        std::fs::create_dir_all(resolved).map_err(|err| io_error(&path, err))?;
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+
+    /// Removes a file or an empty directory.
+    pub(super) fn primitive_remove_file(mut self, runtime: &mut Runtime) -> RunResult {
+        let path = self.dot.needs_string("remove-file needs a path.")?;
+        let resolved = runtime.capabilities.check_write(&path)?;
+        let result = if resolved.is_dir() {
+            std::fs::remove_dir(resolved)
+        } else {
+            std::fs::remove_file(resolved)
+        };
+        result.map_err(|err| io_error(&path, err))?;
+        self.dot = Expr::unit();
+        Ok(self)
+    }
+
+    /// Runs a program with arguments and waits for it to finish. Returns a map
+    /// with its exit `:status` and what it printed to `:stdout` and `:stderr`.
+    pub(super) fn primitive_spawn_process(mut self, runtime: &mut Runtime) -> RunResult {
+        let msg = "spawn-process needs a program and a list of string arguments.";
+        let (program, args) = self.dot.needs_pair(msg)?;
+        let program = program.needs_string(msg)?;
+        let args = args
+            .needs_list(msg)?
+            .into_iter()
+            .map(|arg| arg.needs_string(msg))
+            .collect::<Result<Vec<_>, _>>()?;
+        runtime.capabilities.check_run(&program)?;
+        let output = Command::new(&program)
+            .args(&args)
+            .output()
+            .map_err(|err| io_error(&program, err))?;
+        let status = match output.status.code() {
+            Some(code) => Expr::Number(Number::Int(Int::from(code as i64))),
+            // The process was killed by a signal.
+            None => Expr::Symbol("killed".into()),
+        };
+        let mut result = OrdMap::new();
+        result.insert(Expr::Symbol("status".into()), status);
+        result.insert(
+            Expr::Symbol("stdout".into()),
+            Expr::String(String::from_utf8_lossy(&output.stdout).into_owned()),
+        );
+        result.insert(
+            Expr::Symbol("stderr".into()),
+            Expr::String(String::from_utf8_lossy(&output.stderr).into_owned()),
+        );
+        self.dot = Expr::Map(result);
+        Ok(self)
+    }
+}
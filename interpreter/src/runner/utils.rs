@@ -1,17 +1,18 @@
 use colored::Colorize;
-use im::HashMap;
+use im::OrdMap;
 
-use crate::ast::Asts;
+use crate::number::{Int, Number};
+use num_traits::ToPrimitive;
+use std::rc::Rc;
 
 use super::*;
 
 pub trait ExprMap {
     fn get_symbol(&self, symbol: &str) -> Option<Expr>;
 }
-impl ExprMap for HashMap<Expr, Expr> {
+impl ExprMap for OrdMap<Expr, Expr> {
     fn get_symbol(&self, symbol: &str) -> Option<Expr> {
-        self.get(&Expr::Symbol(symbol.into()))
-            .map(|expr| expr.clone())
+        self.get(&Expr::Symbol(symbol.into())).cloned()
     }
 }
 
@@ -20,10 +21,20 @@ pub fn error<A: Into<String>, B: Into<String>>(kind: A, msg: B) -> Expr {
     Expr::List(vec![Expr::Symbol(kind.into()), Expr::String(msg.into())])
 }
 pub fn wrong_usage<I: Into<String>>(msg: I) -> Expr {
-    error::<&str, I>("wrong-usage".into(), msg)
+    error::<&str, I>("wrong-usage", msg)
 }
 pub fn unknown_function<I: Into<String>>(msg: I) -> Expr {
-    error::<&str, I>("unknown-fun".into(), msg)
+    error::<&str, I>("unknown-fun", msg)
+}
+/// Wraps a value that may be missing, like the result of a lookup.
+pub fn some(value: Expr) -> Expr {
+    Expr::List(vec![Expr::Symbol("some".into()), value])
+}
+pub fn none() -> Expr {
+    Expr::Symbol("none".into())
+}
+pub fn division_by_zero() -> Expr {
+    error("division-by-zero", "Can't divide an exact number by zero.")
 }
 
 pub trait OptionExt<T> {
@@ -35,13 +46,20 @@ impl<T> OptionExt<T> for Option<T> {
     }
 }
 
+#[allow(clippy::wrong_self_convention)]
 impl Expr {
-    pub fn as_number(self) -> Option<i64> {
+    pub fn as_number(self) -> Option<Number> {
         match self {
             Expr::Number(number) => Some(number),
             _ => None,
         }
     }
+    pub fn as_int(self) -> Option<Int> {
+        match self {
+            Expr::Number(Number::Int(int)) => Some(int),
+            _ => None,
+        }
+    }
     pub fn as_string(self) -> Option<String> {
         match self {
             Expr::String(string) => Some(string),
@@ -54,7 +72,7 @@ impl Expr {
             _ => None,
         }
     }
-    pub fn as_map(self) -> Option<HashMap<Expr, Expr>> {
+    pub fn as_map(self) -> Option<OrdMap<Expr, Expr>> {
         match self {
             Expr::Map(map) => Some(map),
             _ => None,
@@ -66,31 +84,47 @@ impl Expr {
             _ => None,
         }
     }
-    pub fn as_code(self) -> Option<(Box<Context>, Asts)> {
+    pub fn as_code(self) -> Option<(Box<Context>, Rc<Chunk>)> {
         match self {
-            Expr::Code { scope, asts } => Some((scope, asts)),
+            Expr::Code { scope, chunk } => Some((scope, chunk)),
             _ => None,
         }
     }
-    pub fn needs_number<I: Into<String>>(self, msg: I) -> Result<i64, Expr> {
+    pub fn needs_number<I: Into<String>>(self, msg: I) -> Result<Number, Expr> {
         self.as_number().ok_or(wrong_usage(msg))
     }
+    pub fn needs_int<I: Into<String>>(self, msg: I) -> Result<Int, Expr> {
+        self.as_int().ok_or(wrong_usage(msg))
+    }
+    pub fn needs_bool<I: Into<String>>(self, msg: I) -> Result<bool, Expr> {
+        let msg: String = msg.into();
+        match self.needs_symbol(msg.clone())?.as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(wrong_usage(msg)),
+        }
+    }
+    /// Needs an integer that can be used as an index, so it's not negative.
+    pub fn needs_index<I: Into<String>>(self, msg: I) -> Result<usize, Expr> {
+        let msg: String = msg.into();
+        self.needs_int(msg.clone())?.to_usize().needed(msg)
+    }
     pub fn needs_string<I: Into<String>>(self, msg: I) -> Result<String, Expr> {
         self.as_string().ok_or(wrong_usage(msg))
     }
     pub fn needs_symbol<I: Into<String>>(self, msg: I) -> Result<String, Expr> {
         self.as_symbol().ok_or(wrong_usage(msg))
     }
-    pub fn needs_map<I: Into<String>>(self, msg: I) -> Result<HashMap<Expr, Expr>, Expr> {
+    pub fn needs_map<I: Into<String>>(self, msg: I) -> Result<OrdMap<Expr, Expr>, Expr> {
         self.as_map().ok_or(wrong_usage(msg))
     }
     pub fn needs_list<I: Into<String>>(self, msg: I) -> Result<Vec<Expr>, Expr> {
         self.as_list().ok_or(wrong_usage(msg))
     }
-    pub fn needs_code<I: Into<String>>(self, msg: I) -> Result<(Box<Context>, Asts), Expr> {
+    pub fn needs_code<I: Into<String>>(self, msg: I) -> Result<(Box<Context>, Rc<Chunk>), Expr> {
         self.as_code().ok_or(wrong_usage(msg))
     }
-    pub fn needs_list_of_numbers<I: Into<String>>(self, msg: I) -> Result<Vec<i64>, Expr> {
+    pub fn needs_list_of_numbers<I: Into<String>>(self, msg: I) -> Result<Vec<Number>, Expr> {
         let msg: String = msg.into();
         let numbers = self.needs_list(msg.clone())?;
         for n in &numbers {
@@ -105,7 +139,7 @@ impl Expr {
         let msg: String = msg.into();
         self.needs_list(msg.clone())?.needs_two_items(msg)
     }
-    pub fn needs_pair_of_numbers<I: Into<String>>(self, msg: I) -> Result<(i64, i64), Expr> {
+    pub fn needs_pair_of_numbers<I: Into<String>>(self, msg: I) -> Result<(Number, Number), Expr> {
         let msg: String = msg.into();
         let (first, second) = self.needs_pair(msg.clone())?;
         let first = first.needs_number(msg.clone())?;
@@ -119,41 +153,29 @@ pub trait ListOfExprExt {
 }
 impl ListOfExprExt for Vec<Expr> {
     fn needs_two_items<I: Into<String>>(self, msg: I) -> Result<(Expr, Expr), Expr> {
-        if self.len() != 2 {
-            return Err(wrong_usage(msg));
+        let mut items = self.into_iter();
+        match (items.next(), items.next(), items.next()) {
+            (Some(first), Some(second), None) => Ok((first, second)),
+            _ => Err(wrong_usage(msg)),
         }
-        // TODO: Make this more efficient.
-        let (first, second) = self.split_at(1);
-        first.to_vec();
-        Ok((
-            first.first().unwrap().clone(),
-            second.first().unwrap().clone(),
-        ))
     }
     fn needs_three_items<I: Into<String>>(self, msg: I) -> Result<(Expr, Expr, Expr), Expr> {
-        if self.len() != 3 {
-            return Err(wrong_usage(msg));
+        let mut items = self.into_iter();
+        match (items.next(), items.next(), items.next(), items.next()) {
+            (Some(first), Some(second), Some(third), None) => Ok((first, second, third)),
+            _ => Err(wrong_usage(msg)),
         }
-        // TODO: Make this more efficient.
-        let (first, second) = self.split_at(1);
-        let (second, third) = second.split_at(1);
-        Ok((
-            first.first().unwrap().clone(),
-            second.first().unwrap().clone(),
-            third.first().unwrap().clone(),
-        ))
     }
 }
 
 pub trait FancyFunsExt {
     fn to_fancy_string(&self) -> String;
 }
-impl FancyFunsExt for HashMap<String, Fun> {
+impl FancyFunsExt for Funs {
     fn to_fancy_string(&self) -> String {
         itertools::join(
-            self.iter().map(|(name, fun)| {
-                format!("{}{}", name.blue(), fun.export_level.to_string().red())
-            }),
+            self.iter()
+                .map(|fun| format!("{}{}", fun.name.blue(), fun.export_level.to_string().red())),
             ", ",
         )
     }
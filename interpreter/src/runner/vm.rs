@@ -0,0 +1,502 @@
+use im::OrdMap;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use super::{compile::*, fibers::Wait, run::Call, runtime::*, utils::*};
+use crate::ast::*;
+
+/// A primitive that runs code, like `map` or `try`. Instead of running the
+/// code itself, it tells the VM which code to run next and gets the result
+/// back. That way, fibers can also wait inside the code.
+pub(super) trait Task {
+    fn start(&mut self, runtime: &mut Runtime) -> Result<Step, Expr>;
+    /// Continues after the code that the task ran finished or panicked.
+    fn resume(&mut self, runtime: &mut Runtime, result: RunResult) -> Result<Step, Expr>;
+}
+pub(super) enum Step {
+    Run(Context, Rc<Chunk>),
+    /// The task is done and this is the context of the primitive.
+    Done(Context),
+}
+impl Step {
+    /// Runs code in its scope with the dot.
+    pub(super) fn run_code(
+        runtime: &mut Runtime,
+        scope: &Context,
+        body: &Rc<Chunk>,
+        dot: Expr,
+    ) -> Self {
+        Step::Run(scope.clone().next(runtime, dot), body.clone())
+    }
+    /// Finishes the task. The context of the primitive returns with the dot.
+    pub(super) fn done(context: &mut Option<Context>, dot: Expr) -> Self {
+        let mut context = context.take().expect("A task can only be done once.");
+        context.dot = dot;
+        Step::Done(context)
+    }
+}
+
+/// How running a fiber ended for now.
+pub(super) enum Outcome {
+    Done(Context),
+    /// The fiber waits. Once the wait is over, it continues with the outcome
+    /// of the call that the context belongs to.
+    Parked(Context, Wait),
+}
+
+/// The calls of a fiber.
+pub(super) struct Stack(Vec<CallFrame>);
+impl Stack {
+    pub(super) fn new(context: Context, chunk: Rc<Chunk>) -> Self {
+        Self(vec![CallFrame::Code(Box::new(CodeFrame::new(
+            chunk, context, None,
+        )))])
+    }
+}
+
+enum CallFrame {
+    Code(Box<CodeFrame>),
+    Task(TaskFrame),
+}
+
+/// A call of a function whose code runs on the VM.
+struct CodeFrame {
+    chunk: Rc<Chunk>,
+    /// The index of the next op to run.
+    ip: usize,
+    context: Context,
+    /// The callers of the tail calls so far. Together with the number of
+    /// times the functions exported by callees have to be exported before
+    /// they reach them.
+    callers: Vec<(Context, u32)>,
+    /// The latest tail calls, oldest first, for the stack trace in case the
+    /// code panics. Consecutive calls from the same place are collapsed, so
+    /// recursive functions don't fill it up.
+    tail_calls: VecDeque<TailCall>,
+    /// The contexts to go back to after the list items, map entries or string
+    /// parts that are currently running.
+    saved: Vec<Context>,
+    /// The results of list items, map entries and string parts.
+    values: Vec<Expr>,
+    /// Where the function was called. Code that a run or a task starts with
+    /// has no call site.
+    call_site: Option<CallSite>,
+    /// The call that the fiber waits for.
+    parked: Option<CallSite>,
+}
+/// A call in tail position. It's shown in stack traces although it replaced
+/// the code of its caller.
+struct TailCall {
+    caller: Context,
+    slot: Slot,
+    span: Span,
+    /// How many times the same call happened right before this one.
+    repeated: u64,
+}
+/// The most tail calls that a code frame remembers.
+const MAX_TAIL_CALLS: usize = 50;
+
+struct TaskFrame {
+    task: Box<dyn Task>,
+    call_site: CallSite,
+    /// The depth when the task started. Panics that the task handles go back
+    /// to it.
+    depth: u64,
+}
+struct CallSite {
+    slot: Slot,
+    span: Span,
+    position: CallPosition,
+}
+impl CallSite {
+    /// Whether the call went one level deeper. Tail calls and calls at the
+    /// end of a list item, map entry or string part don't.
+    fn nested(&self) -> bool {
+        self.position == CallPosition::Inside
+    }
+}
+
+impl CodeFrame {
+    fn new(chunk: Rc<Chunk>, context: Context, call_site: Option<CallSite>) -> Self {
+        Self {
+            chunk,
+            ip: 0,
+            context,
+            callers: vec![],
+            tail_calls: VecDeque::new(),
+            saved: vec![],
+            values: vec![],
+            call_site,
+            parked: None,
+        }
+    }
+
+    fn record_tail_call(&mut self, caller: Context, slot: Slot, span: Span) {
+        if let Some(last) = self.tail_calls.back_mut() {
+            if last.slot == slot && last.span == span && last.caller.source == caller.source {
+                last.caller = caller;
+                last.repeated += 1;
+                return;
+            }
+        }
+        if self.tail_calls.len() == MAX_TAIL_CALLS {
+            self.tail_calls.pop_front();
+        }
+        self.tail_calls.push_back(TailCall {
+            caller,
+            slot,
+            span,
+            repeated: 0,
+        });
+    }
+
+    /// Returns the context after the code ran, with the functions exported
+    /// by the tail calls.
+    fn finish(self, runtime: &mut Runtime) -> Context {
+        let mut context = self.context;
+        for (mut caller, exports) in self.callers.into_iter().rev() {
+            caller.returned(runtime, context, exports);
+            context = caller;
+        }
+        context
+    }
+}
+
+impl Context {
+    /// Runs compiled code in this context. If no fibers are running yet, the
+    /// code runs as the main fiber, together with the fibers it spawns.
+    /// Otherwise, it runs right away as part of the current fiber and can't
+    /// wait.
+    pub fn run(self, runtime: &mut Runtime, chunk: Rc<Chunk>) -> RunResult {
+        if !runtime.scheduler.is_running() {
+            return runtime.run_fibers(self, chunk);
+        }
+        let depth = runtime.depth();
+        runtime.enter_nested_run()?;
+        let mut stack = Stack::new(self, chunk);
+        let result = execute(runtime, &mut stack.0, false, None);
+        runtime.leave_nested_run();
+        match result {
+            Ok(Outcome::Done(context)) => Ok(context),
+            Ok(Outcome::Parked(..)) => unreachable!("Code that can't wait got parked."),
+            Err(error) => {
+                runtime.restore_depth(depth);
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Runs a fiber until it finishes, panics or waits. A fiber that waited
+/// continues with the outcome of the call it waited for.
+pub(super) fn run_fiber(
+    runtime: &mut Runtime,
+    stack: &mut Stack,
+    resumption: Option<Result<Call, Expr>>,
+) -> Result<Outcome, Expr> {
+    execute(runtime, &mut stack.0, true, resumption)
+}
+
+fn execute(
+    runtime: &mut Runtime,
+    frames: &mut Vec<CallFrame>,
+    can_park: bool,
+    resumption: Option<Result<Call, Expr>>,
+) -> Result<Outcome, Expr> {
+    let mut result = match resumption {
+        Some(call) => {
+            let site = match frames.last_mut() {
+                Some(CallFrame::Code(frame)) => frame.parked.take(),
+                _ => None,
+            };
+            let site = site.expect("Only fibers that wait for a call get resumed.");
+            complete(runtime, frames, &site, call, can_park)
+        }
+        None => Ok(None),
+    };
+    loop {
+        match result {
+            Ok(Some(outcome)) => return Ok(outcome),
+            Ok(None) => {}
+            Err(error) => handle(runtime, frames, error)?,
+        }
+        result = step(runtime, frames, can_park);
+    }
+}
+
+/// Runs a single op of the code frame on top.
+fn step(
+    runtime: &mut Runtime,
+    frames: &mut Vec<CallFrame>,
+    can_park: bool,
+) -> Result<Option<Outcome>, Expr> {
+    let frame = match frames.last_mut() {
+        Some(CallFrame::Code(frame)) => frame,
+        _ => unreachable!("Only code frames run ops."),
+    };
+    let chunk = frame.chunk.clone();
+    let op = match chunk.ops.get(frame.ip) {
+        Some(op) => op,
+        None => {
+            let frame = match frames.pop() {
+                Some(CallFrame::Code(frame)) => frame,
+                _ => unreachable!(),
+            };
+            let nested = frame.call_site.as_ref().is_some_and(CallSite::nested);
+            let callee = frame.finish(runtime);
+            if frames.is_empty() {
+                return Ok(Some(Outcome::Done(callee)));
+            }
+            deliver(runtime, frames, Ok(callee), nested)?;
+            return Ok(None);
+        }
+    };
+    frame.ip += 1;
+    match op {
+        Op::Push(value) => frame.context.advance(runtime, value.clone()),
+        Op::Code(chunk) => {
+            let scope = Box::new(frame.context.clone());
+            let chunk = chunk.clone();
+            frame.context.advance(runtime, Expr::Code { scope, chunk });
+        }
+        Op::Nest => runtime.descend()?,
+        Op::Enter => frame.saved.push(frame.context.clone()),
+        Op::Leave => {
+            let saved = frame.saved.pop().expect("Leave needs a saved context.");
+            let context = std::mem::replace(&mut frame.context, saved);
+            frame.values.push(context.dot);
+        }
+        Op::List(len) => {
+            let items = frame.values.split_off(frame.values.len() - len);
+            runtime.ascend();
+            frame.context.advance(runtime, Expr::List(items));
+        }
+        Op::Map(len) => {
+            let mut map = OrdMap::new();
+            let mut entries = frame
+                .values
+                .split_off(frame.values.len() - 2 * len)
+                .into_iter();
+            while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                map.insert(key, value);
+            }
+            runtime.ascend();
+            frame.context.advance(runtime, Expr::Map(map));
+        }
+        Op::Interpolate(pieces) => {
+            let len = pieces
+                .iter()
+                .filter(|piece| matches!(piece, Piece::Value))
+                .count();
+            let mut values = frame.values.split_off(frame.values.len() - len).into_iter();
+            let mut string = String::new();
+            for piece in pieces {
+                match piece {
+                    Piece::Text(text) => string.push_str(text),
+                    Piece::Value => match values.next().expect("A value is missing.") {
+                        Expr::String(text) => string.push_str(&text),
+                        value => string.push_str(&value.to_string()),
+                    },
+                }
+            }
+            runtime.ascend();
+            frame.context.advance(runtime, Expr::String(string));
+        }
+        Op::Fail => return Err(error("parse-error", "This code couldn't be parsed.")),
+        Op::Call {
+            slot,
+            span,
+            position,
+        } => {
+            let call = match *position {
+                CallPosition::Inside => runtime.descend(),
+                _ => Ok(()),
+            }
+            .and_then(|()| frame.context.call(runtime, *slot));
+            let site = CallSite {
+                slot: *slot,
+                span: *span,
+                position: *position,
+            };
+            return complete(runtime, frames, &site, call, can_park);
+        }
+    }
+    Ok(None)
+}
+
+/// Continues after the call at the site in the code frame on top returned,
+/// or continues the call if it's not done yet.
+fn complete(
+    runtime: &mut Runtime,
+    frames: &mut Vec<CallFrame>,
+    site: &CallSite,
+    call: Result<Call, Expr>,
+    can_park: bool,
+) -> Result<Option<Outcome>, Expr> {
+    let frame = match frames.last_mut() {
+        Some(CallFrame::Code(frame)) => frame,
+        _ => unreachable!("Only code frames call functions."),
+    };
+    let call = match call {
+        Ok(Call::Park(..)) if !can_park => Err(wrong_usage(
+            "Guards run right away, so they can't wait for other fibers.",
+        )),
+        call => call,
+    };
+    let call = match call {
+        Ok(call) => call,
+        Err(error) => {
+            runtime.unwind(frame.context.frame(&site.slot.name(), site.span));
+            return Err(error);
+        }
+    };
+    let new_site = || CallSite {
+        slot: site.slot,
+        span: site.span,
+        position: site.position,
+    };
+    match (site.position, call) {
+        (_, Call::Park(callee, wait)) => {
+            frame.parked = Some(new_site());
+            return Ok(Some(Outcome::Parked(callee, wait)));
+        }
+        (_, Call::Task(mut task)) => {
+            let started = task.start(runtime);
+            frames.push(CallFrame::Task(TaskFrame {
+                task,
+                call_site: new_site(),
+                depth: runtime.depth(),
+            }));
+            take_step(runtime, frames, started)?;
+        }
+        (CallPosition::Tail, call) => {
+            let exports_anything = frame.context.funs.exports_anything();
+            match frame.callers.last_mut() {
+                Some((_, exports)) if !exports_anything => {
+                    *exports = exports.saturating_add(1);
+                }
+                _ => frame.callers.push((frame.context.clone(), 1)),
+            }
+            let (callee, body) = match call {
+                Call::Returned(callee) => (callee, None),
+                Call::Continue(callee, body) => (callee, Some(body)),
+                Call::Task(_) | Call::Park(..) => unreachable!(),
+            };
+            let caller = std::mem::replace(&mut frame.context, callee);
+            frame.record_tail_call(caller, site.slot, site.span);
+            // Without a body, the tail call was the last op anyway.
+            if let Some(body) = body {
+                frame.chunk = body;
+                frame.ip = 0;
+            }
+        }
+        (position, Call::Returned(callee)) => {
+            if position == CallPosition::Inside {
+                runtime.ascend();
+            }
+            frame.context.returned(runtime, callee, 1);
+        }
+        (_, Call::Continue(callee, body)) => {
+            let frame = CodeFrame::new(body, callee, Some(new_site()));
+            frames.push(CallFrame::Code(Box::new(frame)));
+        }
+    }
+    Ok(None)
+}
+
+/// Gives the result of the frame that just finished to the frame on top.
+fn deliver(
+    runtime: &mut Runtime,
+    frames: &mut Vec<CallFrame>,
+    result: RunResult,
+    nested: bool,
+) -> Result<(), Expr> {
+    match frames.last_mut() {
+        Some(CallFrame::Code(caller)) => {
+            if nested {
+                runtime.ascend();
+            }
+            let callee = match result {
+                Ok(callee) => callee,
+                Err(_) => unreachable!("Panics are handled before they reach code frames."),
+            };
+            caller.context.returned(runtime, callee, 1);
+            Ok(())
+        }
+        Some(CallFrame::Task(frame)) => {
+            let step = frame.task.resume(runtime, result);
+            take_step(runtime, frames, step)
+        }
+        None => unreachable!("Only the first frame has no caller."),
+    }
+}
+
+/// Continues after the task on top took a step.
+fn take_step(
+    runtime: &mut Runtime,
+    frames: &mut Vec<CallFrame>,
+    step: Result<Step, Expr>,
+) -> Result<(), Expr> {
+    if let Ok(Step::Run(context, chunk)) = step {
+        frames.push(CallFrame::Code(Box::new(CodeFrame::new(
+            chunk, context, None,
+        ))));
+        return Ok(());
+    }
+    let frame = match frames.pop() {
+        Some(CallFrame::Task(frame)) => frame,
+        _ => unreachable!("Only tasks take steps."),
+    };
+    match step {
+        Ok(Step::Done(context)) => deliver(runtime, frames, Ok(context), frame.call_site.nested()),
+        Err(error) => {
+            record_call(runtime, frames, &frame.call_site);
+            Err(error)
+        }
+        Ok(Step::Run(..)) => unreachable!(),
+    }
+}
+
+/// Handles a panic of the frame on top. Tasks like `try` can handle panics of
+/// the code they run. If none does, all frames are left and the panic is
+/// returned. Either way, the calls that the panic leaves are recorded in the
+/// stack trace, including the latest tail calls of each frame.
+fn handle(runtime: &mut Runtime, frames: &mut Vec<CallFrame>, error: Expr) -> Result<(), Expr> {
+    let mut error = error;
+    while let Some(frame) = frames.pop() {
+        match frame {
+            CallFrame::Code(frame) => {
+                for tail_call in frame.tail_calls.iter().rev() {
+                    let mut trace_frame = tail_call
+                        .caller
+                        .frame(&tail_call.slot.name(), tail_call.span);
+                    trace_frame.repeated = tail_call.repeated;
+                    runtime.unwind(trace_frame);
+                }
+                if let Some(site) = &frame.call_site {
+                    record_call(runtime, frames, site);
+                }
+            }
+            CallFrame::Task(mut frame) => {
+                runtime.restore_depth(frame.depth);
+                let step = frame.task.resume(runtime, Err(error));
+                frames.push(CallFrame::Task(frame));
+                match take_step(runtime, frames, step) {
+                    Ok(()) => return Ok(()),
+                    Err(new_error) => error = new_error,
+                }
+            }
+        }
+    }
+    Err(error)
+}
+
+/// Records a call from the code frame on top in the stack trace.
+fn record_call(runtime: &mut Runtime, frames: &[CallFrame], site: &CallSite) {
+    match frames.last() {
+        Some(CallFrame::Code(caller)) => {
+            runtime.unwind(caller.context.frame(&site.slot.name(), site.span))
+        }
+        _ => unreachable!("Calls happen in code frames."),
+    }
+}
@@ -0,0 +1,100 @@
+//! Tests for actors, their mailboxes and how they watch each other.
+
+mod common;
+
+use common::{eval, ok, printed, run};
+
+#[test]
+fn receives_the_first_matching_message() {
+    assert_eq!(
+        eval(
+            "(self, :a) send-message (self, :b) send-message (self, :c) send-message \
+             ((:b, [:got-b]) receive-message, \
+              (:?m, [m]) receive-message, \
+              (:?m, [m]) receive-message)"
+        ),
+        ok("(:got-b, :a, :c)")
+    );
+}
+
+#[test]
+fn keeps_messages_that_dont_match_for_later() {
+    assert_eq!(
+        eval(
+            "(:main, self) let \
+             (0, [(main, :noise) send-message 1 wait (main, (:value, 42)) send-message]) \
+             spawn-actor \
+             (((:value, :?v), [v]) receive-message, (:?m, [m]) receive-message)"
+        ),
+        ok("(42, :noise)")
+    );
+}
+
+#[test]
+fn linked_actors_panic_together() {
+    assert_eq!(
+        eval("(:a, (0, [1 wait :boom panic]) spawn-actor) let a link 10 wait :survived"),
+        Err("(:linked-panic, (:actor, 1), :boom)".to_string())
+    );
+    assert_eq!(
+        eval(
+            "(:a, (0, [1 wait :boom panic]) spawn-actor) let \
+             (:b, (a, [link (:never, [:never]) receive-message]) spawn-actor) let \
+             b monitor \
+             ((:down, :_, :?outcome), [outcome]) receive-message"
+        ),
+        ok("(:error, (:linked-panic, (:actor, 1), :boom))")
+    );
+}
+
+#[test]
+fn monitors_get_told_how_actors_finished() {
+    assert_eq!(
+        eval(
+            "(:a, (41, [(., 1) +]) spawn-actor) let a monitor \
+             ((:down, :?who, :?outcome), [((who, a) =, outcome)]) receive-message"
+        ),
+        ok("(:true, (:ok, 42))")
+    );
+    assert_eq!(
+        eval(
+            "(:a, (0, [:crash panic]) spawn-actor) let a monitor \
+             ((:down, :_, :?outcome), [outcome]) receive-message"
+        ),
+        ok("(:error, :crash)")
+    );
+    assert_eq!(
+        eval(
+            "(:a, (0, [:done]) spawn-actor) let 0 wait a monitor \
+             ((:down, :_, :?outcome), [outcome]) receive-message"
+        ),
+        ok("(:ok, :done)")
+    );
+}
+
+#[test]
+fn supervised_actors_restart_until_they_give_up() {
+    let run = run(
+        "(:s, (0, [\"started\" print :crash panic], 2) supervise) let s monitor \
+         ((:?m, [m]) receive-message, \
+          (:?m, [m]) receive-message, \
+          (:?m, [m]) receive-message)",
+    );
+    assert_eq!(
+        run.result,
+        ok("((:restarted, (:actor, 1), :crash), \
+            (:restarted, (:actor, 1), :crash), \
+            (:down, (:actor, 1), (:error, :crash)))")
+    );
+    assert_eq!(run.output, printed(&["started", "started", "started"]));
+}
+
+#[test]
+fn supervised_actors_that_finish_stay_finished() {
+    let run = run(
+        "(:s, (0, [(:n, .) let \"started\" print (n, 1) +], 2) supervise) let s monitor \
+         (:?m, [m]) receive-message",
+    );
+    assert_eq!(run.result, ok("(:down, (:actor, 1), (:ok, 1))"));
+    assert_eq!(run.output, printed(&["started"]));
+}
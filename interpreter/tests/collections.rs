@@ -0,0 +1,83 @@
+//! Tests for the list and map primitives.
+
+mod common;
+
+use common::{eval, ok};
+
+fn wrong_usage(message: &str) -> Result<String, String> {
+    Err(format!("(:wrong-usage, {:?})", message))
+}
+
+#[test]
+fn get_item_returns_an_option() {
+    assert_eq!(eval("((:a, :b), 1) get-item"), ok("(:some, :b)"));
+    assert_eq!(eval("((:a, :b), 2) get-item"), ok(":none"));
+    assert_eq!(eval("((:a, :b), -1) get-item"), ok(":none"));
+    assert_eq!(
+        eval("((:a, :b), 100000000000000000000000) get-item"),
+        ok(":none")
+    );
+    assert_eq!(eval("((), 0) get-item"), ok(":none"));
+}
+
+#[test]
+fn get_key_returns_an_option() {
+    assert_eq!(eval("({:a, 1}, :a) get-key"), ok("(:some, 1)"));
+    assert_eq!(eval("({:a, 1}, :b) get-key"), ok(":none"));
+    assert_eq!(eval("({}, :a) get-key"), ok(":none"));
+}
+
+#[test]
+fn insert_and_remove_need_existing_positions() {
+    assert_eq!(eval("((1, 3), 1, 2) insert"), ok("(1, 2, 3)"));
+    assert_eq!(eval("((1, 2), 2, 3) insert"), ok("(1, 2, 3)"));
+    assert_eq!(
+        eval("((1, 2), 3, 4) insert"),
+        wrong_usage("insert can't insert at index 3 into a list with length 2.")
+    );
+    assert!(eval("((1, 2), -1, 0) insert").is_err());
+
+    assert_eq!(eval("((1, 2), 0) remove"), ok("(2)"));
+    assert_eq!(
+        eval("((1, 2), 2) remove"),
+        wrong_usage("remove can't remove index 2 from a list with length 2.")
+    );
+    assert!(eval("((1, 2), -1) remove").is_err());
+    assert_eq!(eval("({:a, 1}, :b) remove"), ok("{:a, 1}"));
+}
+
+#[test]
+fn map_filter_and_fold_run_code() {
+    assert_eq!(eval("((1, 2, 3), [(., 1) +]) map"), ok("(2, 3, 4)"));
+    assert_eq!(eval("((), [(., 1) +]) map"), ok("()"));
+    assert_eq!(eval("((1, 2, 3, 4), [(., 2) >]) filter"), ok("(3, 4)"));
+    assert_eq!(eval("((1, 2, 3), 10, [+]) fold"), ok("16"));
+    assert_eq!(
+        eval("((:a, :b), (), [((:acc, :item), .) let (acc, item) push]) fold"),
+        ok("(:a, :b)")
+    );
+    assert_eq!(
+        eval("((1, 2), [:yes]) filter"),
+        wrong_usage("filter needs code that returns a bool.")
+    );
+}
+
+#[test]
+fn panics_in_code_leave_map_filter_and_fold() {
+    assert_eq!(
+        eval("((1, 2, 3), [(., 2, [(:two, 2) panic], :?n, [n]) match]) map"),
+        Err("(:two, 2)".to_string())
+    );
+    assert_eq!(
+        eval("((1, 2), [:oops panic]) filter"),
+        Err(":oops".to_string())
+    );
+    assert_eq!(
+        eval("((1, 2), 0, [:oops panic]) fold"),
+        Err(":oops".to_string())
+    );
+    assert_eq!(
+        eval("[((1, 2), [:oops panic]) map] try"),
+        ok("(:error, :oops)")
+    );
+}
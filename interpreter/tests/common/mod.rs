@@ -0,0 +1,70 @@
+//! Runs Mehl programs together with the core library. The runtime gets a
+//! `MemoryHost`, so tests can check what programs print.
+#![allow(dead_code)]
+
+use mehl::ast::Ast;
+use mehl::runner::*;
+
+pub struct Run {
+    /// The value that the program returned, or the value it panicked with.
+    pub result: Result<String, String>,
+    /// The lines that the program printed.
+    pub output: Vec<String>,
+    /// The lines that the program printed to the standard error.
+    pub errors: Vec<String>,
+    /// The function calls that a panic passed through, innermost first.
+    pub trace: Vec<String>,
+}
+
+pub fn run(source: &str) -> Run {
+    run_on(MemoryHost::default(), Runtime::default(), source)
+}
+
+/// Runs the program and returns its result.
+pub fn eval(source: &str) -> Result<String, String> {
+    run(source).result
+}
+
+/// The result of a program that returned the value.
+pub fn ok(value: &str) -> Result<String, String> {
+    Ok(value.to_string())
+}
+
+/// The output of printing the strings.
+pub fn printed(strings: &[&str]) -> Vec<String> {
+    strings
+        .iter()
+        .map(|string| format!("🌮> {:?}", string))
+        .collect()
+}
+
+pub fn run_on(host: MemoryHost, runtime: Runtime, source: &str) -> Run {
+    let mut runtime = runtime.with_host(host.clone());
+    let core = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/core.mehl"))
+        .expect("Couldn't read core.mehl.");
+    let core = Ast::parse_all(&core).expect("The core library doesn't parse.");
+    let code = Ast::parse_all(source).unwrap_or_else(|errors| {
+        panic!("The test program doesn't parse: {:?}", errors);
+    });
+    let context = Context::root(&mut runtime)
+        .in_source("core.mehl")
+        .run(&mut runtime, Chunk::compile(core))
+        .unwrap_or_else(|error| panic!("The core library panicked: {}", error));
+    let result = context
+        .in_source("test.mehl")
+        .run(&mut runtime, Chunk::compile(code))
+        .map(|context| context.dot.to_string())
+        .map_err(|error| error.to_string());
+    let trace = runtime
+        .take_trace()
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect();
+    let memory = host.memory();
+    Run {
+        result,
+        output: memory.output.clone(),
+        errors: memory.errors.clone(),
+        trace,
+    }
+}
@@ -0,0 +1,162 @@
+//! Tests that CSTs reproduce their source byte for byte and that lowering them
+//! results in the same ASTs as the parser produced before there were CSTs.
+
+use mehl::ast::*;
+
+fn read(file: &str) -> String {
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file);
+    std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Couldn't read {}.", path))
+}
+
+fn assert_round_trip(source: &str) {
+    let (csts, _) = Cst::parse(source);
+    assert_eq!(to_source(&csts), source);
+}
+
+#[test]
+fn files_round_trip() {
+    assert_round_trip(&read("core.mehl"));
+    assert_round_trip(&read("test.mehl"));
+}
+
+#[test]
+fn malformed_code_round_trips() {
+    let sources = [
+        "(1, 2",
+        "1 ) 2 ] }",
+        "[1 (2 ]",
+        "\"abc",
+        "'\"abc\"",
+        "'abc",
+        "\"a {b\"",
+        "\"a {} b\"",
+        "\"\\u{zz}\\q\"",
+        "{1, 2, 3}",
+        "(1, , 2,,)",
+        "12a 2r3 99r1 1/0 1.",
+        "[1, 2]",
+        "# only a comment",
+        "  \n\t\r\n ",
+        "✨ é\u{301} :",
+    ];
+    for source in &sources {
+        assert_round_trip(source);
+    }
+}
+
+#[test]
+fn spans_cover_the_source_of_each_cst() {
+    fn check(source: &str, csts: &[Cst]) {
+        for cst in csts {
+            let text = &source[cst.span.start.offset as usize..cst.span.end.offset as usize];
+            assert_eq!(text, cst.to_string());
+            check(source, cst.children());
+        }
+    }
+    let core = read("core.mehl");
+    check(&core, &Cst::parse(&core).0);
+}
+
+/// Describes ASTs including their spans. Map entries are sorted, because the
+/// parser used to keep them in a hash map.
+fn dump(asts: &[Ast]) -> String {
+    itertools::join(asts.iter().map(dump_ast), " ")
+}
+fn dump_ast(ast: &Ast) -> String {
+    let kind = match &ast.kind {
+        AstKind::Number(number) => format!("Number({})", number),
+        AstKind::String(string) => format!("String({:?})", string),
+        AstKind::InterpolatedString(_) => unreachable!("No snippet contains interpolations."),
+        AstKind::Symbol(symbol) => format!("Symbol({})", symbol),
+        AstKind::Name(name) => format!("Name({})", name),
+        AstKind::List(items) => format!(
+            "List({})",
+            itertools::join(items.iter().map(|item| dump(item)), ", ")
+        ),
+        AstKind::Map(map) => {
+            let mut entries = map
+                .iter()
+                .map(|(key, value)| format!("{}: {}", dump(key), dump(value)))
+                .collect::<Vec<_>>();
+            entries.sort();
+            format!("Map({})", entries.join(", "))
+        }
+        AstKind::Code(code) => format!("Code({})", dump(code)),
+        AstKind::Error => "Error".to_string(),
+    };
+    format!(
+        "{}@{}..{}",
+        kind, ast.span.start.offset, ast.span.end.offset
+    )
+}
+
+#[test]
+fn lowering_produces_the_same_asts_as_the_old_parser() {
+    // The ASTs and the number of errors that the parser produced before
+    // parsing went through CSTs.
+    let expected = [
+        ("", "", 0),
+        ("1 2 3", "Number(1)@0..1 Number(2)@2..3 Number(3)@4..5", 0),
+        ("16rff 2r101", "Number(255)@0..5 Number(5)@6..11", 0),
+        (":foo :", "Symbol(foo)@0..4 Symbol()@5..6", 0),
+        ("\"hi there\"", "String(\"hi there\")@0..10", 0),
+        (
+            "foo bar.baz ✨ .",
+            "Name(foo)@0..3 Name(bar.baz)@4..11 Name(✨)@12..15 Name(.)@16..17",
+            0,
+        ),
+        (
+            "(1, 2, 3)",
+            "List(Number(1)@1..2, Number(2)@4..5, Number(3)@7..8)@0..9",
+            0,
+        ),
+        ("(1,)", "List(Number(1)@1..2)@0..4", 0),
+        ("()", "List()@0..2", 0),
+        (
+            "(1 2, :a b)",
+            "List(Number(1)@1..2 Number(2)@3..4, Symbol(a)@6..8 Name(b)@9..10)@0..11",
+            0,
+        ),
+        (
+            "{:a, 1, :b, (2, 3)}",
+            "Map(Symbol(a)@1..3: Number(1)@5..6, Symbol(b)@8..10: List(Number(2)@13..14, Number(3)@16..17)@12..18)@0..19",
+            0,
+        ),
+        ("{}", "Map()@0..2", 0),
+        (
+            "[1 [2] .]",
+            "Code(Number(1)@1..2 Code(Number(2)@4..5)@3..6 Name(.)@7..8)@0..9",
+            0,
+        ),
+        (
+            "# comment\n1 # trailing\n2",
+            "Number(1)@10..11 Number(2)@23..24",
+            0,
+        ),
+        (
+            "(1, [:a foo], {\"k\", 36rz})",
+            "List(Number(1)@1..2, Code(Symbol(a)@5..7 Name(foo)@8..11)@4..12, Map(String(\"k\")@15..18: Number(35)@20..24)@14..25)@0..26",
+            0,
+        ),
+        ("(1, 2", "List(Number(1)@1..2, Number(2)@4..5)@0..5", 1),
+        ("1 ) 2", "Number(1)@0..1 Number(2)@4..5", 1),
+        (
+            "[1 (2 ]",
+            "Code(Number(1)@1..2 List(Number(2)@4..5)@3..6)@0..7",
+            1,
+        ),
+        ("\"abc", "Error@0..4", 1),
+        (
+            "{1, 2, 3}",
+            "Map(Number(1)@1..2: Number(2)@4..5, Number(3)@7..8: Error@9..9)@0..9",
+            1,
+        ),
+        ("(1, , 2)", "List(Number(1)@1..2, Number(2)@6..7)@0..8", 1),
+        ("12a 3", "Error@0..3 Number(3)@4..5", 1),
+    ];
+    for (source, asts, number_of_errors) in &expected {
+        let (actual, errors) = Ast::parse(source);
+        assert_eq!(dump(&actual), *asts, "{:?}", source);
+        assert_eq!(errors.len(), *number_of_errors, "{:?}", source);
+    }
+}
@@ -0,0 +1,81 @@
+//! Tests for handling panics with `try`, `catch` and `finally`.
+
+mod common;
+
+use common::{eval, ok, printed, run};
+
+#[test]
+fn try_turns_panics_into_values() {
+    assert_eq!(eval("[5] try"), ok("(:ok, 5)"));
+    assert_eq!(eval("[(:oops, 1) panic] try"), ok("(:error, (:oops, 1))"));
+    assert_eq!(
+        eval("[undefined-fun] try"),
+        ok("(:error, (:unknown-fun, \"undefined-fun\"))")
+    );
+    assert_eq!(
+        eval("[[:inner panic] try] try"),
+        ok("(:ok, (:error, :inner))")
+    );
+}
+
+#[test]
+fn catch_only_handles_matching_panics() {
+    assert_eq!(
+        eval("([(:oops, 1) panic], (:oops, :?n), [(n, 1) +]) catch"),
+        ok("2")
+    );
+    assert_eq!(eval("([5], :_, [0]) catch"), ok("5"));
+    assert_eq!(
+        eval("([(:other, 1) panic], (:oops, :_), [0]) catch"),
+        Err("(:other, 1)".to_string())
+    );
+    assert_eq!(
+        eval(
+            "([([(:other, 1) panic], (:oops, :_), [:inner]) catch], (:other, :_), [:outer]) catch"
+        ),
+        ok(":outer")
+    );
+}
+
+#[test]
+fn catch_handlers_get_the_panic_and_can_reraise_it() {
+    assert_eq!(eval("([:oops panic], :_, [.]) catch"), ok(":oops"));
+    assert_eq!(
+        eval("([:oops panic], :_, [(:again, .) panic]) catch"),
+        Err("(:again, :oops)".to_string())
+    );
+    assert_eq!(
+        eval("[([:oops panic], :oops, [. panic]) catch] try"),
+        ok("(:error, :oops)")
+    );
+}
+
+#[test]
+fn finally_runs_cleanup_in_order() {
+    let succeeded = run("([\"body\" print 1], [\"cleanup\" print]) finally");
+    assert_eq!(succeeded.result, ok("1"));
+    assert_eq!(succeeded.output, printed(&["body", "cleanup"]));
+
+    let panicked = run("[([\"body\" print :oops panic], [\"cleanup\" print]) finally] try");
+    assert_eq!(panicked.result, ok("(:error, :oops)"));
+    assert_eq!(panicked.output, printed(&["body", "cleanup"]));
+}
+
+#[test]
+fn panics_in_cleanup_replace_the_result() {
+    assert_eq!(
+        eval("[([1], [:cleanup panic]) finally] try"),
+        ok("(:error, :cleanup)")
+    );
+    assert_eq!(
+        eval("[([:body panic], [:cleanup panic]) finally] try"),
+        ok("(:error, :cleanup)")
+    );
+}
+
+#[test]
+fn finally_runs_before_outer_handlers() {
+    let run =
+        run("([([:oops panic], [\"cleanup\" print]) finally], :oops, [\"handler\" print]) catch");
+    assert_eq!(run.output, printed(&["cleanup", "handler"]));
+}
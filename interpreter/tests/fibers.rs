@@ -0,0 +1,93 @@
+//! Tests for fibers, channels and waiting.
+
+mod common;
+
+use common::{eval, ok, printed, run};
+
+fn wrong_usage(message: &str) -> Result<String, String> {
+    Err(format!("(:wrong-usage, {:?})", message))
+}
+
+#[test]
+fn sending_to_a_full_channel_waits() {
+    let run = run("(:c, 1 channel) let \
+         (:sender, [(c, 1) send \"sent 1\" print (c, 2) send \"sent 2\" print] spawn) let \
+         0 wait \
+         \"receiving\" print \
+         (c receive, sender await, c receive)");
+    assert_eq!(run.result, ok("((:some, 1), \"sent 2\", (:some, 2))"));
+    assert_eq!(run.output, printed(&["sent 1", "receiving", "sent 2"]));
+}
+
+#[test]
+fn receiving_waits_for_a_value() {
+    assert_eq!(
+        eval("(:c, 1 channel) let [1 wait (c, :late) send] spawn c receive"),
+        ok("(:some, :late)")
+    );
+}
+
+#[test]
+fn closed_channels_return_none_once_empty() {
+    assert_eq!(
+        eval("(:c, 2 channel) let (c, 1) send c close (c receive, c receive, c receive)"),
+        ok("((:some, 1), :none, :none)")
+    );
+    assert!(eval("(:c, 1 channel) let c close (c, 1) send").is_err());
+}
+
+#[test]
+fn select_runs_the_case_that_is_ready() {
+    assert_eq!(
+        eval(
+            "(:a, 1 channel) let (:b, 1 channel) let (b, :hi) send \
+             ((a, [(:a, .)]), (b, [(:b, .)])) select"
+        ),
+        ok("(:b, (:some, :hi))")
+    );
+    assert_eq!(
+        eval(
+            "(:a, 1 channel) let (:b, 1 channel) let (a, :full) send \
+             (((a, 1), [:sent-a]), ((b, 2), [:sent-b])) select"
+        ),
+        ok(":sent-b")
+    );
+}
+
+#[test]
+fn await_returns_the_result_or_panics_again() {
+    assert_eq!(eval("[(1, 2) +] spawn await"), ok("3"));
+    assert_eq!(eval("[:oops panic] spawn await"), Err(":oops".to_string()));
+    assert_eq!(
+        eval("[[(:bad, 1) panic] spawn await] try"),
+        ok("(:error, (:bad, 1))")
+    );
+}
+
+#[test]
+fn detects_deadlocks() {
+    assert_eq!(
+        eval("1 channel receive"),
+        Err("(:deadlock, \"All fibers are waiting, so none of them can continue.\")".to_string())
+    );
+    assert_eq!(
+        eval("(:c, 1 channel) let [c receive] spawn await"),
+        Err("(:deadlock, \"All fibers are waiting, so none of them can continue.\")".to_string())
+    );
+}
+
+#[test]
+fn waits_no_longer_than_the_clock_can_count() {
+    assert_eq!(
+        eval("(1 wait, 18446744073709551615 wait)"),
+        wrong_usage("can't wait that long.")
+    );
+    assert_eq!(
+        eval("18446744073709551616 wait"),
+        wrong_usage("can't wait that long.")
+    );
+    assert_eq!(
+        eval("-1 wait"),
+        wrong_usage("can't wait a negative number of seconds.")
+    );
+}
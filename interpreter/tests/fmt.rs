@@ -0,0 +1,173 @@
+//! Tests for `mehl fmt`: the formatted output of some snippets, properties
+//! that hold for all code, and the exit code of `--check`.
+
+use mehl::ast::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn format(source: &str) -> String {
+    format_source(source)
+        .unwrap_or_else(|errors| panic!("{:?} doesn't parse: {:?}", source, errors))
+}
+
+fn read(file: &str) -> String {
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file);
+    std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Couldn't read {}.", path))
+}
+fn mehl_files() -> Vec<String> {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let mut files = vec![read("core.mehl"), read("test.mehl")];
+    for entry in std::fs::read_dir(format!("{}/benches", root)).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() == Some("mehl".as_ref()) {
+            files.push(std::fs::read_to_string(path).unwrap());
+        }
+    }
+    files
+}
+
+const GOLDEN: &[(&str, &str)] = &[
+    (
+        "# Header comment\n\n\n(:a,   1) let    # trailing\n[ 1 2\n  3 ] (1,2,3)\n",
+        "# Header comment\n\n(:a, 1) let # trailing\n[\n  1 2\n  3\n] (1, 2, 3)\n",
+    ),
+    ("[   ]   ()   {}", "[] () {}\n"),
+    ("{:key,\"value\",:other,(1,2)}", "{:key, \"value\", :other, (1, 2)}\n"),
+    (
+        "(:foo, \"docs\", [(., 0) =], [ \"zero\" print  (1, 2) ]) overload",
+        "(:foo, \"docs\", [(., 0) =], [\"zero\" print (1, 2)]) overload\n",
+    ),
+    (
+        "(:long-function-name, \"Some documentation that is quite long indeed\", [(:long-function-name, .) ✨]) fun",
+        "(\n  :long-function-name,\n  \"Some documentation that is quite long indeed\",\n  [(:long-function-name, .) ✨],\n) fun\n",
+    ),
+    (
+        "{:name, \"A fairly long name for a map entry\", :docs, \"And some documentation\", :body, [x]}",
+        "{\n  :name, \"A fairly long name for a map entry\",\n  :docs, \"And some documentation\",\n  :body, [x],\n}\n",
+    ),
+    (
+        "(\n  1, # one\n  # before two\n  2\n)",
+        "(\n  1, # one\n  # before two\n  2,\n)\n",
+    ),
+    (
+        "( # Some numbers.\n  1,\n  2\n)\n[ # Setup.\n  x\n]",
+        "( # Some numbers.\n  1,\n  2,\n)\n[ # Setup.\n  x\n]\n",
+    ),
+    (
+        "{\n  :name, # The key.\n  # The value.\n  \"Foo\",\n  :age, 3 # Years.\n}",
+        "{\n  :name, # The key.\n  # The value.\n  \"Foo\",\n  :age, 3, # Years.\n}\n",
+    ),
+    ("", ""),
+];
+
+#[test]
+fn formats_snippets() {
+    for (source, formatted) in GOLDEN {
+        assert_eq!(format(source), *formatted, "{:?}", source);
+    }
+}
+
+#[test]
+fn does_not_format_code_with_errors() {
+    assert!(format_source("(1, 2").is_err());
+    assert!(format_source("\"abc").is_err());
+}
+
+#[test]
+fn is_idempotent() {
+    let sources = mehl_files()
+        .into_iter()
+        .chain(GOLDEN.iter().map(|(source, _)| source.to_string()));
+    for source in sources {
+        let formatted = format(&source);
+        assert_eq!(format(&formatted), formatted, "{:?}", source);
+    }
+}
+
+#[test]
+fn keeps_the_meaning_and_the_comments() {
+    fn comments(csts: &[Cst], out: &mut Vec<String>) {
+        for cst in csts {
+            if let CstKind::Comment(comment) = &cst.kind {
+                out.push(comment.trim_end().to_string());
+            }
+            comments(cst.children(), out);
+        }
+    }
+    for source in mehl_files() {
+        let formatted = format(&source);
+        assert_eq!(Ast::parse_all(&formatted), Ast::parse_all(&source));
+        let (mut before, mut after) = (vec![], vec![]);
+        comments(&Cst::parse(&source).0, &mut before);
+        comments(&Cst::parse(&formatted).0, &mut after);
+        assert_eq!(after, before);
+    }
+}
+
+#[test]
+fn wraps_at_80_columns() {
+    let items = (0..30).map(|i| i.to_string()).collect::<Vec<_>>();
+    let source = format!("(:numbers, ({})) let", items.join(", "));
+    let formatted = format(&source);
+    assert!(formatted.lines().count() > 1);
+    for line in formatted.lines() {
+        assert!(line.chars().count() <= 80, "{:?} is too long.", line);
+    }
+    // Code that fits stays on one line.
+    let short = "(:numbers, (1, 2, 3)) let\n";
+    assert_eq!(format(short), short);
+}
+
+#[test]
+fn formats_deeply_nested_code() {
+    let depth = MAX_NESTING;
+    let items = (0..depth).map(|i| format!("(:item-{}, ", i));
+    let source = format!("{}:end{}", items.collect::<String>(), ")".repeat(depth));
+    let formatted = format(&source);
+    assert_eq!(format(&formatted), formatted);
+    for line in formatted.lines() {
+        assert!(
+            line.trim_start().chars().count() <= 80,
+            "{:?} is too long.",
+            line
+        );
+    }
+}
+
+fn temp_file(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mehl-fmt-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+fn mehl(args: &[&str], file: &Path) -> std::process::ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_mehl"))
+        .args(args)
+        .arg(file)
+        .output()
+        .expect("Couldn't run mehl.")
+        .status
+}
+
+#[test]
+fn check_exits_with_an_error_for_unformatted_files() {
+    let formatted = temp_file("formatted.mehl", "(1, 2) print\n");
+    let unformatted = temp_file("unformatted.mehl", "(1,2)   print");
+    let broken = temp_file("broken.mehl", "(1, 2");
+
+    assert!(mehl(&["fmt", "--check"], &formatted).success());
+    assert_eq!(mehl(&["fmt", "--check"], &unformatted).code(), Some(1));
+    assert_eq!(mehl(&["fmt", "--check"], &broken).code(), Some(1));
+    // Checking doesn't change the file, but formatting does.
+    assert_eq!(
+        std::fs::read_to_string(&unformatted).unwrap(),
+        "(1,2)   print"
+    );
+    assert!(mehl(&["fmt"], &unformatted).success());
+    assert_eq!(
+        std::fs::read_to_string(&unformatted).unwrap(),
+        "(1, 2) print\n"
+    );
+    assert!(mehl(&["fmt", "--check"], &unformatted).success());
+}
@@ -0,0 +1,99 @@
+//! Tests for destructuring values with `let`.
+
+mod common;
+
+use common::{eval, ok};
+
+fn wrong_usage(message: &str) -> Result<String, String> {
+    Err(format!("(:wrong-usage, {:?})", message))
+}
+
+#[test]
+fn destructures_lists_and_maps() {
+    assert_eq!(eval("((:a, :b), (1, 2)) let (a, b) -"), ok("-1"));
+    assert_eq!(
+        eval("({:x, (:a, {:b, :c})}, {:x, (1, {:b, 2, :d, 3}), :y, 4}) let (a, c) +"),
+        ok("3")
+    );
+    assert_eq!(eval("((:a, :_), (1, 2)) let a"), ok("1"));
+}
+
+#[test]
+fn binds_the_rest_of_a_list() {
+    assert_eq!(eval("((:a, :..rest), (1, 2, 3)) let rest"), ok("(2, 3)"));
+    assert_eq!(eval("((:a, :..rest, :z), (1, 2)) let rest"), ok("()"));
+    assert_eq!(
+        eval("((:..init, :z), (1, 2, 3)) let (init, z)"),
+        ok("((1, 2), 3)")
+    );
+    assert_eq!(eval("((:a, :.., :z), (1, 2, 3)) let (a, z)"), ok("(1, 3)"));
+}
+
+#[test]
+fn reports_where_the_shape_doesnt_match() {
+    assert_eq!(
+        eval("({:x, (:a, {:b, :c})}, {:x, (1, {:c, 2})}) let"),
+        wrong_usage(
+            "let can't destructure {:x, (1, {:c, 2})} into {:x, (:a, {:b, :c})}: \
+             list item 1 of key :x has no key :b."
+        )
+    );
+    assert_eq!(
+        eval("({:x, :a}, (1, 2)) let"),
+        wrong_usage(
+            "let can't destructure (1, 2) into {:x, :a}: the value is (1, 2), which is no map."
+        )
+    );
+    assert_eq!(
+        eval("((:a, (:b, :c)), (1, 2)) let"),
+        wrong_usage(
+            "let can't destructure (1, 2) into (:a, (:b, :c)): \
+             list item 1 is 2, which is no list."
+        )
+    );
+}
+
+#[test]
+fn reports_length_mismatches() {
+    assert_eq!(
+        eval("((:a, :b), (1, 2, 3)) let"),
+        wrong_usage(
+            "let can't destructure (1, 2, 3) into (:a, :b): \
+             the value has a length of 3, but 2 was expected."
+        )
+    );
+    assert_eq!(
+        eval("((:a, :b, :..rest), (1,)) let"),
+        wrong_usage(
+            "let can't destructure (1) into (:a, :b, :..rest): \
+             the value has a length of 1, but at least 2 was expected."
+        )
+    );
+}
+
+#[test]
+fn names_bound_twice_need_equal_values() {
+    assert_eq!(eval("((:a, :a), (1, 1)) let a"), ok("1"));
+    assert_eq!(
+        eval("((:a, (:b, :a)), (1, (2, 3))) let"),
+        wrong_usage(
+            "let can't destructure (1, (2, 3)) into (:a, (:b, :a)): \
+             the value binds a to 3, but it's already bound to 1."
+        )
+    );
+}
+
+#[test]
+fn failed_destructuring_can_be_caught() {
+    assert_eq!(
+        eval("[((:a, :b), 5) let] try"),
+        ok(
+            "(:error, (:wrong-usage, \"let can't destructure 5 into (:a, :b): \
+            the value is 5, which is no list.\"))"
+        )
+    );
+    assert_eq!(
+        eval("([((:a, :b), 5) let], (:wrong-usage, :_), [:caught]) catch"),
+        ok(":caught")
+    );
+}
@@ -0,0 +1,123 @@
+//! Tests for the numeric tower of integers, rationals and floats.
+
+mod common;
+
+use common::{eval, ok};
+
+#[test]
+fn integers_grow_beyond_64_bits() {
+    assert_eq!(
+        eval("(9223372036854775807, 1) +"),
+        ok("9223372036854775808")
+    );
+    assert_eq!(
+        eval("(-9223372036854775807, 2) -"),
+        ok("-9223372036854775809")
+    );
+    assert_eq!(
+        eval("(4294967296, 4294967296, 4294967296) *"),
+        ok("79228162514264337593543950336")
+    );
+    assert_eq!(
+        eval("(79228162514264337593543950336, 4294967296) /"),
+        ok("18446744073709551616")
+    );
+}
+
+#[test]
+fn division_is_exact() {
+    assert_eq!(eval("(1, 3) /"), ok("1/3"));
+    assert_eq!(eval("(6, 3) /"), ok("2"));
+    assert_eq!(eval("((1, 3) /, (2, 3) /) +"), ok("1"));
+    assert_eq!(eval("((1, 2) /, 3) *"), ok("3/2"));
+    assert_eq!(eval("(7, 2) mod"), ok("1"));
+    assert_eq!(eval("(-7, 2) mod"), ok("-1"));
+    assert!(eval("(1, 0) /").is_err());
+    assert!(eval("(1, 0) mod").is_err());
+}
+
+#[test]
+fn floats_are_contagious() {
+    assert_eq!(eval("(1, 0.5) +"), ok("3/2"));
+    assert_eq!(eval("(1, 0.5 to-float) +"), ok("1.5"));
+    assert_eq!(eval("((1, 2) /, 0.25 to-float) +"), ok("0.75"));
+    assert_eq!(eval("(1, 0 to-float) /"), ok("inf"));
+    assert_eq!(eval("1 to-float"), ok("1.0"));
+    assert_eq!(eval("(1, 3) / to-float"), ok("0.3333333333333333"));
+}
+
+#[test]
+fn big_integers_turn_into_floats() {
+    assert_eq!(
+        eval("10000000000000000000000000000000000000 to-float"),
+        ok("10000000000000000000000000000000000000.0")
+    );
+    assert_eq!(
+        eval("-100000000000000000000000000000 to-float"),
+        ok("-100000000000000000000000000000.0")
+    );
+    assert_eq!(
+        eval("(-100000000000000000000000000000, 0 to-float) +"),
+        ok("-100000000000000000000000000000.0")
+    );
+    assert_eq!(
+        eval("(10000000000000000000000000000000000000, 3) / to-float"),
+        ok("3333333333333333400000000000000000000.0")
+    );
+    let huge = format!("1{}", "0".repeat(400));
+    assert_eq!(eval(&format!("{} to-float", huge)), ok("inf"));
+    assert_eq!(eval(&format!("(0, {}) - to-float", huge)), ok("-inf"));
+}
+
+#[test]
+fn floats_are_shown_with_a_decimal_point() {
+    let big = format!("1{}", "0".repeat(40));
+    assert_eq!(
+        eval(&format!(
+            "({} to-float, (1, 0 to-float) /, (-1, 0 to-float) /, (0, 0 to-float) /)",
+            big
+        )),
+        ok("(10000000000000000000000000000000000000000.0, inf, -inf, nan)")
+    );
+    assert_eq!(eval("(1, 10000000) / to-float"), ok("0.0000001"));
+}
+
+#[test]
+fn finite_floats_parse_back_to_the_same_value() {
+    let tiny = format!("(1, 1{}) / to-float", "0".repeat(300));
+    let huge = format!("1{} to-float", "0".repeat(300));
+    for float in [
+        "1 to-float",
+        "-2.5 to-float",
+        "0.1 to-float",
+        "(1, 3) / to-float",
+        "(10000000000000000000000000000000000000, 3) / to-float",
+        &tiny,
+        &huge,
+    ] {
+        assert_eq!(
+            eval(&format!(
+                "(:x, {}) let \
+                 (x number-to-string string-to-number, (:some, :?n), [(n to-float, x) =]) match",
+                float
+            )),
+            ok(":true"),
+            "{}",
+            float
+        );
+    }
+}
+
+#[test]
+fn rounding() {
+    assert_eq!(eval("(7, 2) / floor"), ok("3"));
+    assert_eq!(eval("(-7, 2) / floor"), ok("-4"));
+    assert_eq!(eval("(5, 2) / round"), ok("3"));
+    assert_eq!(eval("(-5, 2) / round"), ok("-3"));
+    assert_eq!(eval("2.5 to-float floor"), ok("2"));
+    assert_eq!(
+        eval("100000000000000000000000000000 to-float round"),
+        ok("99999999999999991433150857216")
+    );
+    assert!(eval("(1, 0 to-float) / floor").is_err());
+}
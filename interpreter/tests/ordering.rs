@@ -0,0 +1,87 @@
+//! Tests for comparing values and the total order over them.
+
+mod common;
+
+use common::{eval, ok};
+
+#[test]
+fn numbers_compare_by_value() {
+    assert_eq!(eval("(1, 2) <"), ok(":true"));
+    assert_eq!(eval("((1, 2) /, 0.75 to-float) <"), ok(":true"));
+    assert_eq!(eval("((1, 2) /, 0.5 to-float) ="), ok(":false"));
+    assert_eq!(eval("(1, 1 to-float) <"), ok(":false"));
+    assert_eq!(eval("(1 to-float, 1) >"), ok(":false"));
+    assert_eq!(eval("(1, 1 to-float) <="), ok(":true"));
+    assert_eq!(eval("((1, 2) /, 0.5 to-float) >="), ok(":true"));
+    // Only compare and sort order equal numbers by their kind.
+    assert_eq!(eval("(1, 1 to-float) compare"), ok(":less"));
+    assert_eq!(eval("(0.5 to-float, (1, 2) /) compare"), ok(":greater"));
+}
+
+#[test]
+fn big_integers_compare_exactly_with_floats() {
+    assert_eq!(
+        eval("(-100000000000000000000000000000, 0 to-float) <"),
+        ok(":true")
+    );
+    assert_eq!(
+        eval("(0 to-float, 100000000000000000000000000000) <"),
+        ok(":true")
+    );
+    // 2^53 + 1 rounds to 2^53 as a float, but it's still bigger.
+    assert_eq!(
+        eval("(9007199254740993, 9007199254740992 to-float) >"),
+        ok(":true")
+    );
+    assert_eq!(
+        eval("(9007199254740993 to-float, 9007199254740993) <"),
+        ok(":true")
+    );
+    assert_eq!(
+        eval("(9007199254740992, 9007199254740992 to-float) compare"),
+        ok(":less")
+    );
+    let huge = format!("1{}", "0".repeat(400));
+    assert_eq!(
+        eval(&format!("({}, (1, 0 to-float) /) <", huge)),
+        ok(":true")
+    );
+    assert_eq!(
+        eval(&format!("((-1, 0 to-float) /, (0, {}) -) <", huge)),
+        ok(":true")
+    );
+}
+
+#[test]
+fn zero_is_between_negative_and_positive_zero() {
+    assert_eq!(eval("((0 to-float, -1) *, 0) <"), ok(":true"));
+    assert_eq!(eval("(0, 0 to-float) <"), ok(":false"));
+    assert_eq!(eval("(0, 0 to-float) compare"), ok(":less"));
+    assert_eq!(
+        eval("(0 to-float, (0 to-float, -1) *, 0) sort"),
+        ok("(-0.0, 0, 0.0)")
+    );
+}
+
+#[test]
+fn sorting_mixes_big_integers_and_floats() {
+    assert_eq!(
+        eval("(0 to-float, -100000000000000000000000000000, 0) sort"),
+        ok("(-100000000000000000000000000000, 0, 0.0)")
+    );
+    assert_eq!(
+        eval(
+            "(1 to-float, 100000000000000000000000000000 to-float, \
+             100000000000000000000000000000, (1, 3) /, 1, -2 to-float) sort"
+        ),
+        ok("(-2.0, 1/3, 1, 1.0, 100000000000000000000000000000.0, 100000000000000000000000000000)")
+    );
+}
+
+#[test]
+fn maps_order_keys_by_value() {
+    assert_eq!(
+        eval("{0 to-float, :a, -100000000000000000000000000000, :b, 0, :c} keys"),
+        ok("(-100000000000000000000000000000, 0, 0.0)")
+    );
+}
@@ -0,0 +1,44 @@
+//! Tests for choosing between overloads of a function.
+
+mod common;
+
+use common::{eval, ok};
+
+#[test]
+fn runs_the_first_overload_whose_guard_accepts() {
+    let describe = "(:describe, \"\", [(., 0) =], [:zero]) overload \
+                    (:describe, \"\", [(., 0) <], [:negative]) overload \
+                    (:describe, \"\", [:true], [:positive]) overload ";
+    assert_eq!(eval(&format!("{describe} 0 describe")), ok(":zero"));
+    assert_eq!(eval(&format!("{describe} -3 describe")), ok(":negative"));
+    assert_eq!(eval(&format!("{describe} 3 describe")), ok(":positive"));
+}
+
+#[test]
+fn earlier_catch_alls_hide_later_overloads() {
+    assert_eq!(
+        eval("(:f, \"\", [:true], [:any]) overload (:f, \"\", [(., 0) =], [:zero]) overload 0 f"),
+        ok(":any")
+    );
+}
+
+#[test]
+fn overloads_with_the_same_guard_replace_each_other() {
+    assert_eq!(
+        eval(
+            "(:f, \"\", [(., 0) =], [:old]) overload \
+             (:f, \"\", [:true], [:other]) overload \
+             (:f, \"\", [(., 0) =], [:new]) overload \
+             (0 f, 1 f)"
+        ),
+        ok("(:new, :other)")
+    );
+}
+
+#[test]
+fn complains_if_no_overload_accepts() {
+    assert_eq!(
+        eval("(:f, \"\", [(., 0) =], [:zero]) overload 1 f"),
+        Err("(:no-matching-overload, \"No overload of f accepts 1.\")".to_string())
+    );
+}
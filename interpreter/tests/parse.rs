@@ -0,0 +1,334 @@
+//! Tests that the parser records spans, recovers from invalid code and
+//! reports every error with the right span.
+
+use mehl::ast::*;
+use mehl::number::Int;
+
+/// The errors of parsing the source as kinds and byte ranges.
+fn errors(source: &str) -> Vec<(ParseErrorKind, u32, u32)> {
+    let (_, errors) = Ast::parse(source);
+    errors
+        .into_iter()
+        .map(|error| (error.kind, error.span.start.offset, error.span.end.offset))
+        .collect()
+}
+fn position(offset: u32, line: u32, column: u32) -> Position {
+    Position {
+        offset,
+        line,
+        column,
+    }
+}
+
+#[test]
+fn valid_code_has_no_errors() {
+    let core = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/core.mehl")).unwrap();
+    assert_eq!(Ast::parse(&core).1, vec![]);
+}
+
+#[test]
+fn spans_cover_the_parsed_code() {
+    let asts = Ast::parse_all("1\n  (:a, [x])").unwrap();
+    assert_eq!(
+        asts.iter().map(|ast| ast.span).collect::<Vec<_>>(),
+        vec![
+            Span::new(position(0, 0, 0), position(1, 0, 1)),
+            Span::new(position(4, 1, 2), position(13, 1, 11)),
+        ]
+    );
+    assert_eq!(span_of(&asts), Some(asts[0].span.to(asts[1].span)));
+    assert_eq!(
+        ast_at(&asts, 10).map(|ast| &ast.kind),
+        Some(&AstKind::Name("x".into()))
+    );
+}
+
+#[test]
+fn unclosed_brackets() {
+    for (source, bracket, closing) in
+        &[("(1, 2", '(', ")"), ("[1 2", '[', "]"), ("{1, 2", '{', "}")]
+    {
+        let (_, errors) = Ast::parse(source);
+        let end = position(source.len() as u32, 0, source.len() as u32);
+        assert_eq!(
+            errors,
+            vec![ParseError::new(
+                Span::new(end, end),
+                ParseErrorKind::Unclosed {
+                    bracket: *bracket,
+                    opened: position(0, 0, 0),
+                },
+                vec![Expected::Text(closing.to_string())],
+            )],
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn unclosed_bracket_remembers_where_it_was_opened() {
+    let (_, errors) = Ast::parse("1\n  [2 3\n");
+    assert_eq!(
+        errors[0].kind,
+        ParseErrorKind::Unclosed {
+            bracket: '[',
+            opened: position(4, 1, 2),
+        }
+    );
+    assert_eq!(errors[0].span.start, position(9, 2, 0));
+}
+
+#[test]
+fn stray_closing_brackets() {
+    assert_eq!(
+        errors("1 ) 2 ]"),
+        vec![
+            (ParseErrorKind::UnexpectedCharacter(')'), 2, 3),
+            (ParseErrorKind::UnexpectedCharacter(']'), 6, 7),
+        ]
+    );
+    let (asts, _) = Ast::parse("1 ) 2");
+    let kinds = asts.into_iter().map(|ast| ast.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            AstKind::Number(Int::from(1).into()),
+            AstKind::Number(Int::from(2).into())
+        ]
+    );
+}
+
+#[test]
+fn closing_bracket_of_an_outer_bracket_closes_the_inner_one() {
+    // The `)` belongs to the list, so only the code is unclosed.
+    let (asts, errors) = Ast::parse("([1 2) 3");
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        errors[0].kind,
+        ParseErrorKind::Unclosed { bracket: '[', .. }
+    ));
+    assert_eq!(errors[0].span.start.offset, 5);
+    assert_eq!(asts.len(), 2);
+}
+
+#[test]
+fn unterminated_strings() {
+    assert_eq!(
+        errors("\"abc"),
+        vec![(ParseErrorKind::UnterminatedString, 0, 4)]
+    );
+    let (_, errors) = Ast::parse("x '\"abc\" y");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, ParseErrorKind::UnterminatedString);
+    assert_eq!(errors[0].expected, vec![Expected::Text("\"'".into())]);
+    assert_eq!(
+        (errors[0].span.start.offset, errors[0].span.end.offset),
+        (2, 10)
+    );
+}
+
+#[test]
+fn invalid_escape_sequences() {
+    assert_eq!(
+        errors(r#""a\qb\u{110000}""#),
+        vec![
+            (ParseErrorKind::InvalidEscapeSequence(r"\q".into()), 2, 4),
+            (
+                ParseErrorKind::InvalidEscapeSequence(r"\u{110000}".into()),
+                5,
+                15
+            ),
+        ]
+    );
+}
+
+#[test]
+fn bad_numbers() {
+    assert_eq!(
+        errors("12a4"),
+        vec![(
+            ParseErrorKind::InvalidDigit {
+                digit: 'a',
+                radix: 10
+            },
+            2,
+            3
+        )]
+    );
+    assert_eq!(
+        errors("99r1"),
+        vec![(ParseErrorKind::InvalidRadix("99".into()), 0, 2)]
+    );
+    assert_eq!(
+        errors("2r102"),
+        vec![(
+            ParseErrorKind::InvalidDigit {
+                digit: '2',
+                radix: 2
+            },
+            4,
+            5
+        )]
+    );
+    assert_eq!(errors("1."), vec![(ParseErrorKind::MissingDigits, 2, 2)]);
+    assert_eq!(errors("-"), vec![]);
+    assert_eq!(errors("1/0"), vec![(ParseErrorKind::ZeroDenominator, 2, 3)]);
+    assert_eq!(errors("1_000_000 16rff_ff 1_0.2_5"), vec![]);
+    let misplaced_underscore = |radix| ParseErrorKind::InvalidDigit { digit: '_', radix };
+    assert_eq!(errors("1__0"), vec![(misplaced_underscore(10), 1, 2)]);
+    assert_eq!(errors("1_"), vec![(misplaced_underscore(10), 1, 2)]);
+    assert_eq!(errors("16r_f"), vec![(misplaced_underscore(16), 3, 4)]);
+    assert_eq!(errors("1_.5"), vec![(misplaced_underscore(10), 1, 2)]);
+}
+
+#[test]
+fn malformed_lists_and_maps() {
+    assert_eq!(
+        errors("(1, , 2)"),
+        vec![(ParseErrorKind::MissingListItem, 4, 5)]
+    );
+    assert_eq!(
+        errors("{1, 2, 3}"),
+        vec![(ParseErrorKind::OddMapEntryCount, 0, 9)]
+    );
+    assert_eq!(
+        errors("{1, , 2, 3}"),
+        vec![(ParseErrorKind::MissingMapItem, 4, 5)]
+    );
+}
+
+#[test]
+fn reports_all_errors_in_order() {
+    let source = "(12a, \"x\\q\")\n] {1}\n[\"abc";
+    assert_eq!(
+        errors(source),
+        vec![
+            (
+                ParseErrorKind::InvalidDigit {
+                    digit: 'a',
+                    radix: 10
+                },
+                3,
+                4
+            ),
+            (ParseErrorKind::InvalidEscapeSequence(r"\q".into()), 8, 10),
+            (ParseErrorKind::UnexpectedCharacter(']'), 13, 14),
+            (ParseErrorKind::OddMapEntryCount, 15, 18),
+            (ParseErrorKind::UnterminatedString, 20, 24),
+            (
+                ParseErrorKind::Unclosed {
+                    bracket: '[',
+                    opened: position(19, 2, 0)
+                },
+                24,
+                24
+            ),
+        ]
+    );
+}
+
+#[test]
+fn diagnostics_count_columns_in_utf16() {
+    let source = "\"🌮\"\n\"🌮\" 12a";
+    let (_, errors) = Ast::parse(source);
+    assert_eq!(errors[0].span.start.column, 6);
+    let range = errors[0].to_diagnostic(source).range;
+    assert_eq!((range.start.line, range.start.character), (1, 7));
+    assert_eq!((range.end.line, range.end.character), (1, 8));
+}
+
+#[test]
+fn columns_count_chars_on_long_lines() {
+    let source = format!("\"{}\" 12a \"{}\" 3b", "é".repeat(100), "🌮".repeat(50));
+    let (_, errors) = Ast::parse(&source);
+    let starts = errors
+        .iter()
+        .map(|error| error.span.start)
+        .collect::<Vec<_>>();
+    assert_eq!(starts, vec![position(205, 0, 105), position(411, 0, 161)]);
+}
+
+#[test]
+fn deeply_nested_code_is_reported_instead_of_overflowing_the_stack() {
+    let nested = |depth: usize| "(".repeat(depth) + &")".repeat(depth);
+    assert_eq!(errors(&nested(MAX_NESTING)), vec![]);
+    assert!(format_source(&nested(MAX_NESTING)).is_ok());
+
+    let too_deep = nested(MAX_NESTING + 1);
+    let innermost = MAX_NESTING as u32;
+    assert_eq!(
+        errors(&too_deep),
+        vec![(ParseErrorKind::NestedTooDeeply, innermost, innermost + 1)]
+    );
+    for source in [
+        nested(5000),
+        "(".repeat(5000),
+        "[{(".repeat(3000),
+        "\"{".repeat(3000),
+    ] {
+        let (_, errors) = Ast::parse(&source);
+        let too_deep = errors
+            .iter()
+            .filter(|error| error.kind == ParseErrorKind::NestedTooDeeply);
+        assert_eq!(too_deep.count(), 1);
+        assert!(format_source(&source).is_err());
+    }
+}
+
+/// A small random number generator, so that every run tests the same inputs.
+struct Random(u64);
+impl Random {
+    fn next(&mut self) -> u64 {
+        // SplitMix64
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+    fn char(&mut self) -> char {
+        loop {
+            if let Some(c) = std::char::from_u32((self.next() % 0x11_0000) as u32) {
+                return c;
+            }
+        }
+    }
+}
+
+/// Generates code from pieces that are likely to trip up the parser, mixed
+/// with random chars.
+fn random_source(random: &mut Random) -> String {
+    const PIECES: &[&str] = &[
+        "(", ")", "[", "]", "{", "}", ",", ":", "\"", "'", "\\", "#", " ", "\n", "-", ".", "/",
+        "r", "u{", "0", "9", "a", "Z", "_", "✨", "é", "\u{301}", "foo", "36r", ":sym", "\"{x}\"",
+    ];
+    let len = random.below(40);
+    (0..len)
+        .map(|_| {
+            if random.below(16) == 0 {
+                random.char().to_string()
+            } else {
+                PIECES[random.below(PIECES.len())].to_string()
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn never_panics_on_random_input() {
+    for seed in 0..5_000 {
+        let mut random = Random(seed);
+        let source = random_source(&mut random);
+        let result = std::panic::catch_unwind(|| {
+            let (_, errors) = Ast::parse(&source);
+            for error in errors {
+                let _ = error.render(&source);
+            }
+        });
+        assert!(result.is_ok(), "The parser panicked for {:?}.", source);
+    }
+}
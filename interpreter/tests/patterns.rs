@@ -0,0 +1,61 @@
+//! Tests for matching values against patterns.
+
+mod common;
+
+use common::{eval, ok};
+
+#[test]
+fn type_checks_always_return_bools() {
+    assert_eq!(eval(":_ symbol?"), ok(":true"));
+    assert_eq!(eval(":?name symbol?"), ok(":true"));
+    assert_eq!(eval(":_ bool?"), ok(":false"));
+    assert_eq!(eval(":_ number?"), ok(":false"));
+    assert_eq!(
+        eval("(:_ symbol?, :true, [:yes], :false, [:no]) match"),
+        ok(":yes")
+    );
+}
+
+#[test]
+fn typed_patterns_only_match_values_of_the_type() {
+    let classify = "(:classify, \"\", [
+        (., (:?n, :number) typed, [n], (:_, :string) typed, [:string], :_, [:other]) match
+    ]) fun ";
+    assert_eq!(eval(&format!("{}5 classify", classify)), ok("5"));
+    assert_eq!(eval(&format!("{}\"a\" classify", classify)), ok(":string"));
+    assert_eq!(eval(&format!("{}:a classify", classify)), ok(":other"));
+    assert_eq!(
+        eval("((1, 2), ((:?a, :int) typed, (:?b, :int) typed), [(a, b) +]) match"),
+        ok("3")
+    );
+    assert!(eval("(:?n, :number) typed symbol?").is_ok());
+    assert!(eval("(:n, :number) typed").is_err());
+    assert!(eval("(:?n, :float) typed").is_err());
+    assert!(eval("((:?n, :number) typed, :int) typed").is_err());
+}
+
+#[test]
+fn map_patterns_are_open() {
+    assert_eq!(eval("({:a, 1, :b, 2}, {}, [:any]) match"), ok(":any"));
+    assert_eq!(eval("({:a, 1, :b, 2}, {:a, :?a}, [a]) match"), ok("1"));
+    assert_eq!(
+        eval("({:a, 1}, {:a, :?a, :b, :_}, [a], :_, [:missing]) match"),
+        ok(":missing")
+    );
+}
+
+#[test]
+fn map_patterns_can_capture_the_rest_or_be_closed() {
+    assert_eq!(
+        eval("({:a, 1, :b, 2}, {:a, :_, :.., :?rest}, [rest]) match"),
+        ok("{:b, 2}")
+    );
+    assert_eq!(
+        eval("({:a, 1, :b, 2}, {:a, :_, :.., :none}, [:closed], :_, [:open]) match"),
+        ok(":open")
+    );
+    assert_eq!(
+        eval("({:a, 1}, {:a, :_, :.., :none}, [:closed], :_, [:open]) match"),
+        ok(":closed")
+    );
+}
@@ -0,0 +1,92 @@
+//! Tests for tail calls, the recursion depth limit and stack traces of
+//! recursive functions.
+
+mod common;
+
+use common::{eval, ok, run, run_on};
+use mehl::runner::*;
+
+#[test]
+fn tail_calls_dont_nest() {
+    assert_eq!(
+        eval("(:f, \"\", [(:n, .) let (n, 0, [0], :_, [(n, 1) - f]) match]) fun 70000 f"),
+        ok("0")
+    );
+    assert_eq!(
+        eval(
+            "(:count-down, \"\", [(., 0) =], [:done]) overload \
+             (:count-down, \"\", [:true], [(., 1) - count-down]) overload \
+             10000 count-down"
+        ),
+        ok(":done")
+    );
+}
+
+#[test]
+fn nested_calls_are_limited() {
+    let deep = "(:f, \"\", [(:n, .) let (n, 0, [0], :_, [((n, 1) - f, 1) +]) match]) fun 1000 f";
+    assert_eq!(eval(deep), ok("1000"));
+    let limited = run_on(
+        MemoryHost::default(),
+        Runtime::default().with_max_depth(500),
+        deep,
+    );
+    assert_eq!(
+        limited.result,
+        Err("(:stack-overflow, \"The maximum depth of 500 nested calls was exceeded.\")".into())
+    );
+}
+
+#[test]
+fn nested_guards_are_limited() {
+    // Guards run nested on the native stack, so this runs on a stack that's
+    // as big as the one of the runner in `mehl run`.
+    let nested = |levels: u64| {
+        let source = format!(
+            "(:f, \"\", [((:n, :k), .) let (n, 0, [:true], :_, \
+             [(((((n, 1) -, k),), k) map, (:ok,)) =]) match], [:ok]) overload \
+             ({}, [f]) f",
+            levels
+        );
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(move || eval(&source))
+            .unwrap()
+            .join()
+            .unwrap()
+    };
+    assert_eq!(nested(Runtime::MAX_NESTED_RUNS - 1), ok(":ok"));
+    assert_eq!(
+        nested(Runtime::MAX_NESTED_RUNS * 10),
+        Err("(:stack-overflow, \"Guards can be nested at most 100 levels deep.\")".into())
+    );
+}
+
+#[test]
+fn traces_contain_tail_calls() {
+    let trace = run("(:g, \"\", [:x panic]) fun (:f, \"\", [g]) fun f").trace;
+    assert_eq!(
+        trace
+            .iter()
+            .filter(|frame| frame.starts_with("test.mehl"))
+            .collect::<Vec<_>>(),
+        vec![
+            "test.mehl:1:14 in panic, called with :x",
+            "test.mehl:1:36 in g, called with :",
+            "test.mehl:1:44 in f, called with :",
+        ]
+    );
+}
+
+#[test]
+fn traces_of_deep_tail_recursion_stay_short() {
+    let trace =
+        run("(:f, \"\", [(:n, .) let (n, 0, [:done panic], :_, [(n, 1) - f]) match]) fun 10000 f")
+            .trace;
+    assert!(trace.len() < 100, "The trace has {} frames.", trace.len());
+    let own = trace.iter().find(|frame| frame.starts_with("test.mehl"));
+    assert_eq!(
+        own.map(String::as_str),
+        Some("test.mehl:1:37 in panic, called with :done")
+    );
+}
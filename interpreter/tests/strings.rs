@@ -0,0 +1,70 @@
+//! Tests for the string primitives.
+
+mod common;
+
+use common::{eval, ok};
+
+#[test]
+fn find_counts_graphemes() {
+    assert_eq!(eval(r#"("Hello", "l") find"#), ok("(:some, 2)"));
+    assert_eq!(eval(r#"("🇩🇪 and 🇫🇷", "and") find"#), ok("(:some, 2)"));
+    assert_eq!(eval(r#"("Hello", "x") find"#), ok(":none"));
+    assert_eq!(eval(r#"("Hello", "") find"#), ok("(:some, 0)"));
+}
+
+#[test]
+fn find_only_matches_whole_graphemes() {
+    // "e\u{301}" is an e with a combining accent, so it's a single grapheme.
+    assert_eq!(eval("(\"cafe\u{301}\", \"e\") find"), ok(":none"));
+    assert_eq!(
+        eval("(\"cafe\u{301}\", \"e\u{301}\") find"),
+        ok("(:some, 3)")
+    );
+    assert_eq!(
+        eval("(\"e\u{301} e\", \"e\") find"),
+        ok("(:some, 2)"),
+        "The first e is part of a grapheme, the second one isn't."
+    );
+    assert_eq!(eval("(\"\u{301}x\", \"x\") find"), ok("(:some, 1)"));
+}
+
+#[test]
+fn string_to_number_parses_number_literals() {
+    assert_eq!(eval(r#""42" string-to-number"#), ok("(:some, 42)"));
+    assert_eq!(eval(r#""-1_000" string-to-number"#), ok("(:some, -1000)"));
+    assert_eq!(eval(r#""16rff" string-to-number"#), ok("(:some, 255)"));
+    assert_eq!(eval(r#""1/3" string-to-number"#), ok("(:some, 1/3)"));
+    assert_eq!(eval(r#"" 7\n" string-to-number"#), ok("(:some, 7)"));
+}
+
+#[test]
+fn string_to_number_rejects_everything_else() {
+    for string in &[
+        "5 # comment",
+        "1 2",
+        "(1)",
+        "[1]",
+        "abc",
+        "",
+        "-",
+        "12a",
+        "1/0",
+        "1.",
+    ] {
+        assert_eq!(
+            eval(&format!("{:?} string-to-number", string)),
+            ok(":none"),
+            "{:?}",
+            string
+        );
+    }
+}
+
+#[test]
+fn primitives_with_three_arguments_need_exactly_three() {
+    assert_eq!(eval(r#"("Hello", 1, 3) slice"#), ok("\"el\""));
+    assert_eq!(eval(r#"("a-b-c", "-", "+") replace"#), ok("\"a+b+c\""));
+    assert!(eval(r#"("Hello", 1) slice"#).is_err());
+    assert!(eval(r#"("Hello", 1, 3, 4) slice"#).is_err());
+    assert!(eval(r#"("a-b-c", "-") replace"#).is_err());
+}
@@ -0,0 +1,147 @@
+//! Tests for accessing files and processes only with the granted capabilities.
+
+mod common;
+
+use common::{ok, run_on};
+use mehl::runner::*;
+use std::path::PathBuf;
+
+/// A temporary directory with a `dir` that can be granted and a `secret` file
+/// next to it. It's removed once the test is over.
+struct Sandbox(PathBuf);
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("mehl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::write(root.join("dir/file.txt"), "inside").unwrap();
+        std::fs::write(root.join("secret"), "outside").unwrap();
+        Self(root)
+    }
+    fn path(&self, path: &str) -> String {
+        self.0.join(path).to_string_lossy().into_owned()
+    }
+}
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn eval_with(capabilities: Capabilities, source: &str) -> Result<String, String> {
+    let runtime = Runtime::default().with_capabilities(capabilities);
+    run_on(MemoryHost::default(), runtime, source).result
+}
+
+fn permission_denied(message: &str) -> Result<String, String> {
+    Err(format!("(:permission-denied, {:?})", message))
+}
+
+#[test]
+fn nothing_is_allowed_by_default() {
+    let sandbox = Sandbox::new("default");
+    let file = sandbox.path("dir/file.txt");
+    let dir = sandbox.path("dir");
+    let denied = |source: String, message: String| {
+        assert_eq!(
+            eval_with(Capabilities::default(), &source),
+            permission_denied(&message)
+        );
+    };
+    denied(
+        format!("{:?} read-file", file),
+        format!("Reading {} needs --allow-read.", file),
+    );
+    denied(
+        format!("{:?} list-dir", dir),
+        format!("Reading {} needs --allow-read.", dir),
+    );
+    denied(
+        format!("{:?} exists?", file),
+        format!("Reading {} needs --allow-read.", file),
+    );
+    denied(
+        format!("({:?}, \"hi\") write-file", file),
+        format!("Writing {} needs --allow-write.", file),
+    );
+    denied(
+        format!("{:?} create-dir", sandbox.path("dir/new")),
+        format!("Writing {} needs --allow-write.", sandbox.path("dir/new")),
+    );
+    denied(
+        format!("{:?} remove-file", file),
+        format!("Writing {} needs --allow-write.", file),
+    );
+    denied(
+        "(\"git\", (\"status\",)) spawn-process".to_string(),
+        "Running git needs --allow-run.".to_string(),
+    );
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "inside");
+}
+
+#[test]
+fn granted_directories_can_be_used() {
+    let sandbox = Sandbox::new("granted");
+    let capabilities = Capabilities::default()
+        .allow_read(sandbox.path("dir"))
+        .unwrap()
+        .allow_write(sandbox.path("dir"))
+        .unwrap();
+    assert_eq!(
+        eval_with(
+            capabilities,
+            &format!(
+                "({:?}, \"new\") write-file ({:?} read-file, {:?} list-dir)",
+                sandbox.path("dir/new.txt"),
+                sandbox.path("dir/file.txt"),
+                sandbox.path("dir"),
+            )
+        ),
+        ok("(\"inside\", (\"file.txt\", \"new.txt\"))")
+    );
+}
+
+#[test]
+fn parent_directories_dont_escape_granted_ones() {
+    let sandbox = Sandbox::new("parent");
+    let capabilities = Capabilities::default()
+        .allow_read(sandbox.path("dir"))
+        .unwrap();
+    let escaping = sandbox.path("dir/../secret");
+    assert_eq!(
+        eval_with(capabilities, &format!("{:?} read-file", escaping)),
+        permission_denied(&format!("Reading {} needs --allow-read.", escaping))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_links_dont_escape_granted_directories() {
+    let sandbox = Sandbox::new("symlink");
+    std::os::unix::fs::symlink(sandbox.path("secret"), sandbox.path("dir/link")).unwrap();
+    let capabilities = Capabilities::default()
+        .allow_read(sandbox.path("dir"))
+        .unwrap();
+    let link = sandbox.path("dir/link");
+    assert_eq!(
+        eval_with(capabilities, &format!("{:?} read-file", link)),
+        permission_denied(&format!("Reading {} needs --allow-read.", link))
+    );
+}
+
+#[test]
+fn programs_need_to_be_allowed_by_their_exact_name() {
+    let capabilities = || Capabilities::default().allow_run("git");
+    assert_eq!(
+        eval_with(capabilities(), "(\"ls\", ()) spawn-process"),
+        permission_denied("Running ls needs --allow-run.")
+    );
+    assert_eq!(
+        eval_with(capabilities(), "(\"./git\", ()) spawn-process"),
+        permission_denied("Running ./git needs --allow-run.")
+    );
+    assert_eq!(
+        eval_with(capabilities(), "(\"/usr/bin/git\", ()) spawn-process"),
+        permission_denied("Running /usr/bin/git needs --allow-run.")
+    );
+}
@@ -0,0 +1,63 @@
+//! Tests for virtual time and for scheduling fibers in a seeded order.
+
+mod common;
+
+use common::{ok, run, run_on};
+use mehl::runner::*;
+use std::time::{Duration, Instant};
+
+#[test]
+fn virtual_time_passes_instantly() {
+    let started = Instant::now();
+    let run = run("(:start, now) let 3600 wait (now, start) -");
+    assert_eq!(run.result, ok("3600.0"));
+    assert!(started.elapsed() < Duration::from_secs(60));
+}
+
+#[test]
+fn virtual_clocks_jump_to_the_deadline() {
+    let mut clock = VirtualClock::default();
+    clock.sleep_until(Duration::from_secs(3600));
+    assert_eq!(clock.now(), Duration::from_secs(3600));
+    clock.sleep_until(Duration::from_secs(10));
+    assert_eq!(clock.now(), Duration::from_secs(3600));
+}
+
+#[test]
+fn waiting_fibers_wake_up_in_the_order_of_their_deadlines() {
+    let run = run("(:c, 3 channel) let \
+         [3 wait (c, :third) send] spawn \
+         [1 wait (c, :first) send] spawn \
+         [2 wait (c, :second) send] spawn \
+         (c receive, c receive, c receive, now)");
+    assert_eq!(
+        run.result,
+        ok("((:some, :first), (:some, :second), (:some, :third), 3.0)")
+    );
+}
+
+/// Runs fibers that each print their name, let the others run and then print
+/// their name again. Returns the order of the names.
+fn interleaving(seed: u64) -> Vec<String> {
+    let run = run_on(
+        MemoryHost::default(),
+        Runtime::default().with_seed(seed),
+        "((\"a\", \"b\", \"c\", \"d\"), [(:name, .) let [name print 0 wait name print] spawn]) map \
+         (., [await]) map",
+    );
+    assert!(run.result.is_ok(), "{:?}", run.result);
+    run.output
+}
+
+#[test]
+fn the_same_seed_interleaves_fibers_the_same_way() {
+    for seed in 0..10 {
+        assert_eq!(interleaving(seed), interleaving(seed));
+    }
+}
+
+#[test]
+fn different_seeds_can_interleave_fibers_differently() {
+    let first = interleaving(0);
+    assert!((1..20).any(|seed| interleaving(seed) != first));
+}